@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+
+/// Replaces comments with spaces, keeping lines and columns.
+pub fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                output.push_str("  ");
+                chars.next();
+                while let Some(next) = chars.peek() {
+                    if *next == '\n' {
+                        break;
+                    }
+                    output.push(' ');
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                output.push_str("  ");
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    output.push(if next == '\n' { '\n' } else { ' ' });
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+struct Branch {
+    /// Whether the enclosing code is compiled.
+    parent: bool,
+    /// Whether one of the branches was taken already.
+    taken: bool,
+    active: bool,
+    else_seen: bool,
+    line: usize,
+}
+
+/// Lines of `source` without comments, the lines of disabled `#if` branches
+/// and the conditional directives themselves being blank. The other
+/// directives are left to naga. Errors are `line:column: message`.
+pub fn resolve(source: &str) -> Result<Vec<String>, String> {
+    let stripped = strip_comments(source);
+    let lines: Vec<&str> = stripped.lines().collect();
+    let mut macros = predefined_macros(&lines);
+    let mut stack: Vec<Branch> = Vec::new();
+    let mut output = Vec::with_capacity(lines.len());
+
+    for (index, line) in lines.iter().enumerate() {
+        let number = index + 1;
+        let active = stack.last().is_none_or(|branch| branch.active);
+        let error = |message: &str| format!("{}:1: {}", number, message);
+
+        let directive = match line.trim_start().strip_prefix('#') {
+            Some(directive) => directive.trim_start(),
+            None => {
+                output.push(if active { line.to_string() } else { String::new() });
+                continue;
+            }
+        };
+        let name_end = directive.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(directive.len());
+        let (name, argument) = (&directive[..name_end], directive[name_end..].trim());
+
+        match name {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = active
+                    && match name {
+                        "if" => evaluate(argument, &macros).map_err(|message| error(&message))? != 0,
+                        "ifdef" => macros.contains_key(argument),
+                        _ => !macros.contains_key(argument),
+                    };
+                stack.push(Branch {
+                    parent: active,
+                    taken: condition,
+                    active: condition,
+                    else_seen: false,
+                    line: number,
+                });
+            }
+            "elif" | "else" => {
+                let branch = stack.last_mut().ok_or_else(|| error(&format!("#{} without #if", name)))?;
+                if branch.else_seen {
+                    return Err(error(&format!("#{} after #else", name)));
+                }
+                let condition = branch.parent
+                    && !branch.taken
+                    && (name == "else" || evaluate(argument, &macros).map_err(|message| error(&message))? != 0);
+                branch.active = condition;
+                branch.taken |= condition;
+                branch.else_seen = name == "else";
+            }
+            "endif" => {
+                stack.pop().ok_or_else(|| error("#endif without #if"))?;
+            }
+            _ if !active => (),
+            "define" => {
+                let name_end = argument.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(argument.len());
+                let (macro_name, value) = argument.split_at(name_end);
+                // function-like macros can't be evaluated in conditions
+                let value = if value.starts_with('(') { None } else { Some(value.trim().to_string()) };
+                macros.insert(macro_name.to_string(), value);
+            }
+            "undef" => {
+                macros.remove(argument);
+            }
+            "include" => return Err(error("#include is not supported at build time, see ShaderPreprocessor")),
+            _ => (),
+        }
+
+        let conditional = matches!(name, "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif");
+        output.push(if active && !conditional { line.to_string() } else { String::new() });
+    }
+
+    match stack.last() {
+        Some(branch) => Err(format!("{}:1: #if without #endif", branch.line)),
+        None => Ok(output),
+    }
+}
+
+/// `__VERSION__` from the `#version` line, 110 without one, and `GL_ES`.
+fn predefined_macros(lines: &[&str]) -> HashMap<String, Option<String>> {
+    let version = lines
+        .iter()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .and_then(|line| line.strip_prefix('#'))
+        .and_then(|line| line.trim_start().strip_prefix("version"))
+        .unwrap_or("");
+    let mut parts = version.split_whitespace();
+    let number = parts.next().and_then(|number| number.parse::<i64>().ok()).unwrap_or(110);
+    let es = number == 100 || parts.next() == Some("es");
+
+    let mut macros = HashMap::new();
+    macros.insert("__VERSION__".to_string(), Some(number.to_string()));
+    if es {
+        macros.insert("GL_ES".to_string(), Some("1".to_string()));
+    }
+    macros
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    Operator(&'static str),
+}
+
+const OPERATORS: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")"];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while !rest.is_empty() {
+        let first = rest.chars().next().unwrap_or(' ');
+        if first.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let digits = rest[..end].trim_end_matches(['u', 'U']);
+            let number = digits.parse().map_err(|_| format!("Invalid number {} in #if", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if first.is_ascii_alphabetic() || first == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Identifier(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .ok_or_else(|| format!("Unsupported {:?} in #if", first))?;
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Value of an `#if` expression, undefined names being 0 as in C.
+fn evaluate(expression: &str, macros: &HashMap<String, Option<String>>) -> Result<i64, String> {
+    evaluate_at_depth(expression, macros, 0)
+}
+
+fn evaluate_at_depth(expression: &str, macros: &HashMap<String, Option<String>>, depth: usize) -> Result<i64, String> {
+    if depth > 16 {
+        return Err("Recursive macro in #if".into());
+    }
+
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser { tokens: &tokens, position: 0, macros, depth };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("Unexpected {:?} in #if", token)),
+        None => Ok(value),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    macros: &'a HashMap<String, Option<String>>,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Operator(found)) if *found == operator => Ok(()),
+            _ => Err(format!("Expected {} in #if", operator)),
+        }
+    }
+
+    /// Binary operators of precedence `level` and above.
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: &[&[&str]] = &[&["||"], &["&&"], &["==", "!="], &["<", ">", "<=", ">="], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut value = self.binary(level + 1)?;
+        while let Some(operator) = self.peek_operator().filter(|operator| LEVELS[level].contains(operator)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            value = match operator {
+                "||" => (value != 0 || right != 0) as i64,
+                "&&" => (value != 0 && right != 0) as i64,
+                "==" => (value == right) as i64,
+                "!=" => (value != right) as i64,
+                "<" => (value < right) as i64,
+                ">" => (value > right) as i64,
+                "<=" => (value <= right) as i64,
+                ">=" => (value >= right) as i64,
+                "+" => value.wrapping_add(right),
+                "-" => value.wrapping_sub(right),
+                "*" => value.wrapping_mul(right),
+                _ if right == 0 => return Err("Division by zero in #if".into()),
+                "/" => value / right,
+                _ => value % right,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next().cloned() {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Operator("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("(")) => {
+                let value = self.binary(0)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(Token::Identifier(name)) if name == "defined" => {
+                let parenthesized = self.peek_operator() == Some("(");
+                if parenthesized {
+                    self.position += 1;
+                }
+                let defined = match self.next() {
+                    Some(Token::Identifier(name)) => self.macros.contains_key(name),
+                    _ => return Err("Expected a name after defined in #if".into()),
+                };
+                if parenthesized {
+                    self.expect(")")?;
+                }
+                Ok(defined as i64)
+            }
+            Some(Token::Identifier(name)) => match self.macros.get(&name) {
+                Some(Some(value)) if !value.is_empty() => evaluate_at_depth(value, self.macros, self.depth + 1),
+                Some(_) => Err(format!("{} has no value to test in #if", name)),
+                None => Ok(0),
+            },
+            Some(token) => Err(format!("Unexpected {:?} in #if", token)),
+            None => Err("Incomplete #if expression".into()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{resolve, strip_comments};
+
+    #[test]
+    fn resolves_conditionals_and_comments() {
+        assert_eq!(strip_comments("a // b\nc /* d\ne */ f"), "a     \nc     \n     f");
+
+        let source = "#version 300 es\n#define LEVEL 2\n#if LEVEL > 1 && defined(GL_ES)\na\n#elif 1\nb\n#else\nc\n#endif\n\
+                      #ifdef MISSING\n#if garbage (\n#endif\nd\n#else\ne\n#endif";
+        let lines = resolve(source).unwrap();
+        let code: Vec<&str> = lines.iter().map(|line| line.as_str()).filter(|line| !line.is_empty()).collect();
+        assert_eq!(code, vec!["#version 300 es", "#define LEVEL 2", "a", "e"]);
+        assert_eq!(lines.len(), source.lines().count());
+
+        assert!(resolve("#if __VERSION__ >= 330\na\n").unwrap_err().starts_with("1:1: #if without #endif"));
+        assert!(resolve("#else\n").unwrap_err().starts_with("1:1:"));
+        assert!(resolve("#define F(x) x\n#if F(1)\n#endif\n").unwrap_err().starts_with("2:1:"));
+    }
+
+}
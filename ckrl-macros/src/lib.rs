@@ -0,0 +1,137 @@
+extern crate proc_macro;
+
+mod conditional;
+mod lower;
+
+use std::path::PathBuf;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+use crate::lower::{validate, Declaration, Stage};
+
+/// Validates a GLSL file at build time and expands to a `ckrl::reflect::ShaderInfo`.
+///
+/// The path is relative to the crate root. The stage comes from the extension
+/// (`.vert`, `.vs`, `.frag`, `.fs`) or a second argument:
+/// `include_shader!("shaders/sprite.glsl", fragment)`.
+#[proc_macro]
+pub fn include_shader(input: TokenStream) -> TokenStream {
+    let IncludeInput { path, stage } = parse_macro_input!(input as IncludeInput);
+
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".into());
+    let full_path = PathBuf::from(root).join(path.value());
+
+    let stage = match stage {
+        Some(stage) => parse_stage(&stage),
+        None => full_path
+            .extension()
+            .and_then(|extension| Stage::parse(&extension.to_string_lossy()))
+            .ok_or_else(|| syn::Error::new(path.span(), "Unknown shader stage, add `vertex` or `fragment`")),
+    };
+    let stage = match stage {
+        Ok(stage) => stage,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let source = match std::fs::read_to_string(&full_path) {
+        Ok(source) => source,
+        Err(err) => {
+            let message = format!("Failed to read {}: {}", full_path.display(), err);
+            return syn::Error::new(path.span(), message).to_compile_error().into();
+        }
+    };
+
+    let full_path = full_path.to_string_lossy().to_string();
+    expand(&source, stage, &path, quote!(include_str!(#full_path)), &full_path)
+}
+
+/// Validates inline GLSL at build time and expands to a `ckrl::reflect::ShaderInfo`:
+/// `shader!(vertex, "#version 330 core ...")`.
+#[proc_macro]
+pub fn shader(input: TokenStream) -> TokenStream {
+    let InlineInput { stage, source } = parse_macro_input!(input as InlineInput);
+
+    let stage = match parse_stage(&stage) {
+        Ok(stage) => stage,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    expand(&source.value(), stage, &source, quote!(#source), "inline shader")
+}
+
+fn expand(source: &str, stage: Stage, span: &LitStr, source_tokens: TokenStream2, name: &str) -> TokenStream {
+    let reflection = match validate(source, stage) {
+        Ok(reflection) => reflection,
+        Err(errors) => {
+            let errors = errors.iter().map(|error| {
+                syn::Error::new(span.span(), format!("{}:{}", name, error)).to_compile_error()
+            });
+            return quote!({ #(#errors)* }).into();
+        }
+    };
+
+    let stage = match stage {
+        Stage::Vertex => quote!(::ckrl::gl::ShaderStage::Vertex),
+        Stage::Fragment => quote!(::ckrl::gl::ShaderStage::Fragment),
+    };
+    let attributes = reflection.attributes.iter().map(|Declaration { name, ty, location }| {
+        let location = match location {
+            Some(location) => quote!(Some(#location)),
+            None => quote!(None),
+        };
+        quote!(::ckrl::reflect::ShaderAttribute { name: #name, ty: #ty, location: #location })
+    });
+    let uniforms = reflection
+        .uniforms
+        .iter()
+        .map(|Declaration { name, ty, .. }| quote!(::ckrl::reflect::ShaderUniform { name: #name, ty: #ty }));
+
+    quote!(::ckrl::reflect::ShaderInfo {
+        source: #source_tokens,
+        stage: #stage,
+        attributes: &[#(#attributes),*],
+        uniforms: &[#(#uniforms),*],
+    })
+    .into()
+}
+
+fn parse_stage(stage: &Ident) -> syn::Result<Stage> {
+    Stage::parse(&stage.to_string())
+        .ok_or_else(|| syn::Error::new(stage.span(), "Expected `vertex` or `fragment`"))
+}
+
+struct IncludeInput {
+    path: LitStr,
+    stage: Option<Ident>,
+}
+
+impl Parse for IncludeInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let stage = if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(IncludeInput { path, stage })
+    }
+}
+
+struct InlineInput {
+    stage: Ident,
+    source: LitStr,
+}
+
+impl Parse for InlineInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let stage = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let source = input.parse()?;
+        Ok(InlineInput { stage, source })
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use naga::front::glsl::{Frontend, Options};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+use crate::conditional::resolve;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Vertex,
+    Fragment,
+}
+
+impl Stage {
+    pub fn parse(name: &str) -> Option<Stage> {
+        match name {
+            "vertex" | "vert" | "vs" | "vsh" => Some(Stage::Vertex),
+            "fragment" | "frag" | "fs" | "fsh" => Some(Stage::Fragment),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: String,
+    pub ty: String,
+    /// Set by `layout(location = n)` in the original source.
+    pub location: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reflection {
+    /// Vertex shader inputs.
+    pub attributes: Vec<Declaration>,
+    /// Plain uniforms, samplers and uniform blocks, blocks having type `block`.
+    pub uniforms: Vec<Declaration>,
+}
+
+/// Checks `source` with naga, errors being `line:column: message` in the original source.
+pub fn validate(source: &str, stage: Stage) -> Result<Reflection, Vec<String>> {
+    let lowered = lower(source, stage)?;
+
+    let naga_stage = match stage {
+        Stage::Vertex => naga::ShaderStage::Vertex,
+        Stage::Fragment => naga::ShaderStage::Fragment,
+    };
+
+    let module = Frontend::default()
+        .parse(&Options::from(naga_stage), &lowered.source)
+        .map_err(|err| {
+            err.errors
+                .iter()
+                .map(|error| {
+                    let location = error.meta.location(&lowered.source);
+                    lowered.format_error(location.line_number as usize, location.line_position as usize, &error.kind.to_string())
+                })
+                .collect::<Vec<_>>()
+        })?;
+
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|err| {
+            let mut message = err.as_inner().to_string();
+            let mut source = std::error::Error::source(err.as_inner());
+            while let Some(cause) = source {
+                message.push_str(": ");
+                message.push_str(&cause.to_string());
+                source = cause.source();
+            }
+
+            match err.spans().next() {
+                Some((span, _)) => {
+                    let location = span.location(&lowered.source);
+                    vec![lowered.format_error(location.line_number as usize, location.line_position as usize, &message)]
+                }
+                None => vec![message],
+            }
+        })?;
+
+    Ok(lowered.reflection)
+}
+
+/// Source rewritten into the Vulkan flavoured GLSL naga accepts: `#version 450`,
+/// explicit `in`/`out` locations, plain uniforms wrapped in blocks and
+/// samplers split into textures and samplers.
+#[derive(Debug, Default)]
+struct Lowered {
+    source: String,
+    /// Original line of each line, 0 for generated ones.
+    lines: Vec<usize>,
+    reflection: Reflection,
+}
+
+impl Lowered {
+    fn push(&mut self, line: &str, original: usize) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.lines.push(original);
+    }
+
+    fn format_error(&self, line: usize, column: usize, message: &str) -> String {
+        match line.checked_sub(1).and_then(|index| self.lines.get(index)) {
+            Some(original) if *original > 0 => format!("{}:{}: {}", original, column, message),
+            _ => format!("generated code: {}", message),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    inputs: u32,
+    outputs: u32,
+    bindings: u32,
+}
+
+/// Replacement of the first `length` bytes of a global statement.
+struct Rewrite {
+    length: usize,
+    text: String,
+    /// Put after the last line of the statement.
+    defines: Vec<String>,
+}
+
+fn lower(source: &str, stage: Stage) -> Result<Lowered, Vec<String>> {
+    let mut lowered = Lowered::default();
+    let mut counters = Counters::default();
+    let lines = resolve(source).map_err(|err| vec![err])?;
+
+    let version = lines.iter().position(|line| !line.trim().is_empty());
+    let version = version.filter(|index| lines[*index].trim().starts_with("#version"));
+
+    lowered.push("#version 450 core", version.map_or(0, |index| index + 1));
+    if stage == Stage::Fragment && lines.iter().any(|line| line.contains("gl_FragColor")) {
+        lowered.push("layout(location = 0) out vec4 _FragColor;", 0);
+        lowered.push("#define gl_FragColor _FragColor", 0);
+        counters.outputs = 1;
+    }
+
+    let code = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| if Some(index) == version { "" } else { line.as_str() })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut output = String::with_capacity(code.len());
+    let mut copied = 0;
+    let mut defines: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut errors = Vec::new();
+    for statement in global_statements(&code) {
+        match lower_statement(&code[statement.clone()], stage, &mut counters, &mut lowered.reflection) {
+            Ok(Some(rewrite)) => {
+                let replaced = &code[statement.start..statement.start + rewrite.length];
+                output.push_str(&code[copied..statement.start]);
+                output.push_str(&rewrite.text);
+                output.push_str(&"\n".repeat(replaced.matches('\n').count()));
+                copied = statement.start + rewrite.length;
+
+                let last_line = code[..statement.end].matches('\n').count();
+                defines.entry(last_line).or_default().extend(rewrite.defines);
+            }
+            Ok(None) => (),
+            Err(message) => {
+                let line_start = code[..statement.start].rfind('\n').map_or(0, |index| index + 1);
+                let line = code[..statement.start].matches('\n').count() + 1;
+                errors.push(format!("{}:{}: {}", line, statement.start - line_start + 1, message));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    output.push_str(&code[copied..]);
+
+    for (index, line) in output.split('\n').enumerate() {
+        if Some(index) == version {
+            continue;
+        }
+        lowered.push(&rename_legacy_functions(line), index + 1);
+        for define in defines.remove(&index).unwrap_or_default() {
+            lowered.push(&define, index + 1);
+        }
+    }
+
+    Ok(lowered)
+}
+
+/// Byte ranges of the declarations and function definitions at global scope,
+/// directives excluded.
+fn global_statements(code: &str) -> Vec<Range<usize>> {
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut function = false;
+    let mut skip_line = false;
+
+    for (index, c) in code.char_indices() {
+        if skip_line {
+            skip_line = c != '\n';
+            continue;
+        }
+        let statement_start = match start {
+            Some(start) => start,
+            None if c == '#' && depth == 0 => {
+                skip_line = true;
+                continue;
+            }
+            None if c.is_whitespace() => continue,
+            None => {
+                start = Some(index);
+                index
+            }
+        };
+
+        match c {
+            '{' => {
+                if depth == 0 {
+                    function = code[statement_start..index].trim_end().ends_with(')');
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 && function {
+                    statements.push(statement_start..index + 1);
+                    start = None;
+                }
+            }
+            ';' if depth == 0 => {
+                statements.push(statement_start..index + 1);
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    statements
+}
+
+fn lower_statement(
+    statement: &str,
+    stage: Stage,
+    counters: &mut Counters,
+    reflection: &mut Reflection,
+) -> Result<Option<Rewrite>, String> {
+    let code = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+    if code.starts_with("precision") {
+        return Ok(code.contains("sampler").then(|| Rewrite {
+            length: statement.len(),
+            text: String::new(),
+            defines: Vec::new(),
+        }));
+    }
+
+    let declaration = match parse_declaration(&code)? {
+        Some(declaration) => declaration,
+        None => return Ok(None),
+    };
+
+    if declaration.block {
+        if !matches!(declaration.storage, "uniform" | "buffer") {
+            return Err("in and out blocks are not supported, declare each variable instead".into());
+        }
+        let name = match declaration.ty {
+            Some(name) if name != "{" => name.trim_end_matches('{'),
+            _ => return Err("Blocks need a name".into()),
+        };
+
+        let layout = match declaration.layout {
+            Some(layout) if layout.contains("binding") => format!("layout({})", layout),
+            Some(layout) => format!("layout({}, binding = {})", layout, counters.bindings),
+            None => format!("layout(binding = {})", counters.bindings),
+        };
+        counters.bindings += 1;
+        if declaration.storage == "uniform" {
+            reflection.uniforms.push(Declaration {
+                name: name.to_string(),
+                ty: "block".into(),
+                location: None,
+            });
+        }
+
+        let head = declaration.rest.split('{').next().unwrap_or("").trim();
+        return Ok(Some(Rewrite {
+            length: statement.find('{').unwrap_or(0),
+            text: format!("{} {} ", layout, head),
+            defines: Vec::new(),
+        }));
+    }
+
+    let ty = match declaration.ty {
+        Some(ty) => ty,
+        None => return Ok(None),
+    };
+    let explicit_location = declaration.layout.and_then(parse_location);
+    let mut output = String::new();
+    let mut defines = Vec::new();
+
+    for name in &declaration.names {
+        let base_name = name.split('[').next().unwrap_or(name).trim();
+
+        match declaration.storage {
+            "uniform" => {
+                reflection.uniforms.push(Declaration {
+                    name: base_name.to_string(),
+                    ty: ty.to_string(),
+                    location: explicit_location,
+                });
+
+                match texture_types(ty) {
+                    Some((texture, sampler)) => {
+                        output.push_str(&format!(
+                            "layout(binding = {}) uniform {} _{}_texture; layout(binding = {}) uniform {} _{}_sampler; ",
+                            counters.bindings,
+                            texture,
+                            base_name,
+                            counters.bindings + 1,
+                            sampler,
+                            base_name
+                        ));
+                        defines.push(format!(
+                            "#define {} {}(_{}_texture, _{}_sampler)",
+                            base_name, ty, base_name, base_name
+                        ));
+                        counters.bindings += 2;
+                    }
+                    None => {
+                        output.push_str(&format!(
+                            "layout(binding = {}) uniform _{}_block {{ {} {}; }}; ",
+                            counters.bindings, base_name, ty, name
+                        ));
+                        counters.bindings += 1;
+                    }
+                }
+            }
+            storage => {
+                let input = match storage {
+                    "in" | "attribute" => true,
+                    "varying" => stage == Stage::Fragment,
+                    _ => false,
+                };
+                if input && stage == Stage::Vertex {
+                    reflection.attributes.push(Declaration {
+                        name: base_name.to_string(),
+                        ty: ty.to_string(),
+                        location: explicit_location,
+                    });
+                }
+
+                let counter = if input { &mut counters.inputs } else { &mut counters.outputs };
+                let layout = match declaration.layout {
+                    Some(layout) if explicit_location.is_some() => layout.to_string(),
+                    Some(layout) => format!("{}, location = {}", layout, counter),
+                    None => format!("location = {}", counter),
+                };
+                *counter += location_count(ty, name);
+
+                output.push_str(&format!(
+                    "layout({}) {} {} {} {}; ",
+                    layout,
+                    declaration.qualifiers.join(" "),
+                    if input { "in" } else { "out" },
+                    ty,
+                    name
+                ));
+            }
+        }
+    }
+
+    Ok(Some(Rewrite {
+        length: statement.len(),
+        text: output.trim_end().to_string(),
+        defines,
+    }))
+}
+
+/// `texture2D(` and friends, a define would also rename the `texture2D` type.
+fn rename_legacy_functions(line: &str) -> String {
+    line.replace("texture2DLod(", "textureLod(")
+        .replace("texture2D(", "texture(")
+        .replace("textureCube(", "texture(")
+}
+
+struct ParsedDeclaration<'a> {
+    layout: Option<&'a str>,
+    storage: &'a str,
+    /// Interpolation and precision qualifiers.
+    qualifiers: Vec<&'a str>,
+    ty: Option<&'a str>,
+    names: Vec<&'a str>,
+    /// Uniform or buffer block, `ty` being its name.
+    block: bool,
+    /// Statement after the layout.
+    rest: &'a str,
+}
+
+const STORAGES: &[&str] = &["uniform", "buffer", "in", "out", "attribute", "varying"];
+const QUALIFIERS: &[&str] = &["flat", "smooth", "noperspective", "centroid", "highp", "mediump", "lowp", "invariant"];
+
+/// `None` for statements other than declarations with a storage qualifier,
+/// an error for the declaration forms the lowering can't rewrite.
+fn parse_declaration(code: &str) -> Result<Option<ParsedDeclaration<'_>>, String> {
+    let (layout, rest) = if let Some(after) = code.strip_prefix("layout") {
+        let after = after.trim_start().strip_prefix('(').ok_or("Expected ( after layout")?;
+        let end = after.find(')').ok_or("Unterminated layout qualifier")?;
+        (Some(after[..end].trim()), after[end + 1..].trim())
+    } else {
+        (None, code)
+    };
+
+    let tokens: Vec<&str> = rest.split_whitespace().collect();
+    let storage_index = match tokens.iter().position(|token| STORAGES.contains(token)) {
+        Some(index) if tokens[..index].iter().all(|token| QUALIFIERS.contains(token)) => index,
+        _ => return Ok(None),
+    };
+    let storage = tokens[storage_index];
+
+    let block = rest.contains('{');
+    let mut qualifiers: Vec<&str> = tokens[..storage_index].to_vec();
+    let mut remaining = tokens[storage_index + 1..].iter().copied().peekable();
+    while let Some(token) = remaining.peek() {
+        if QUALIFIERS.contains(token) {
+            qualifiers.push(token);
+            remaining.next();
+        } else {
+            break;
+        }
+    }
+    let ty = remaining.next();
+
+    if block {
+        return Ok(Some(ParsedDeclaration {
+            layout,
+            storage,
+            qualifiers,
+            ty,
+            names: Vec::new(),
+            block,
+            rest,
+        }));
+    }
+
+    let names: String = remaining.collect::<Vec<_>>().join(" ");
+    let names = names.strip_suffix(';').unwrap_or(&names).trim();
+    if names.contains('=') {
+        return Err(match storage {
+            "uniform" => "Uniform initializers are not supported, set the value from code".into(),
+            storage => format!("{} variables can't have initializers", storage),
+        });
+    }
+    if ty.is_some() && names.is_empty() {
+        return Err(format!("Expected a name after {}", ty.unwrap_or("")));
+    }
+    let declared = rest.strip_suffix(';').unwrap_or(rest).trim_end();
+    let names: Vec<&str> = declared[declared.len() - names.len()..]
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    Ok(Some(ParsedDeclaration {
+        layout,
+        storage,
+        qualifiers: qualifiers.into_iter().filter(|q| !matches!(*q, "highp" | "mediump" | "lowp")).collect(),
+        ty,
+        names,
+        block,
+        rest,
+    }))
+}
+
+fn parse_location(layout: &str) -> Option<u32> {
+    layout.split(',').find_map(|part| {
+        let mut parts = part.splitn(2, '=');
+        if parts.next()?.trim() == "location" {
+            parts.next()?.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// `sampler2D` to `texture2D` and `sampler`, `sampler2DShadow` to `texture2D`
+/// and `samplerShadow`.
+fn texture_types(ty: &str) -> Option<(String, &'static str)> {
+    let index = ty.find("sampler")?;
+    let (prefix, dimensions) = (&ty[..index], &ty[index + "sampler".len()..]);
+    if dimensions.is_empty() || dimensions == "Shadow" {
+        return None;
+    }
+
+    let shadow = dimensions.ends_with("Shadow");
+    let dimensions = dimensions.trim_end_matches("Shadow");
+    let sampler = if shadow { "samplerShadow" } else { "sampler" };
+    Some((format!("{}texture{}", prefix, dimensions), sampler))
+}
+
+/// Locations taken by a varying, matrices using one per column.
+fn location_count(ty: &str, name: &str) -> u32 {
+    let columns = match ty {
+        "mat2" | "mat2x2" | "mat2x3" | "mat2x4" => 2,
+        "mat3" | "mat3x2" | "mat3x3" | "mat3x4" => 3,
+        "mat4" | "mat4x2" | "mat4x3" | "mat4x4" => 4,
+        _ => 1,
+    };
+    let elements = name
+        .split('[')
+        .nth(1)
+        .and_then(|size| size.trim_end_matches(']').trim().parse().ok())
+        .unwrap_or(1);
+    columns * elements
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{validate, Declaration, Stage};
+
+    #[test]
+    fn accepts_core_es_and_legacy_glsl() {
+        let vertex = "#version 330 core\nlayout (location = 0) in vec3 aPos;\nin vec2 aTexCoord;\nuniform mat4 Transform;\nout vec2 TexCoord;\n\
+                      void main()\n{\n    TexCoord = aTexCoord;\n    gl_Position = Transform * vec4(aPos, 1.0);\n}\n";
+        let reflection = validate(vertex, Stage::Vertex).unwrap();
+        assert_eq!(
+            reflection.attributes,
+            vec![
+                Declaration { name: "aPos".into(), ty: "vec3".into(), location: Some(0) },
+                Declaration { name: "aTexCoord".into(), ty: "vec2".into(), location: None },
+            ]
+        );
+        assert_eq!(reflection.uniforms[0].name, "Transform");
+
+        let fragment = "#version 300 es\nprecision mediump float;\nin vec2 TexCoord;\nuniform sampler2D Texture;\nuniform float Smoothing;\n\
+                        out vec4 FragColor;\nvoid main()\n{\n    FragColor = texture(Texture, TexCoord) * Smoothing;\n}\n";
+        let reflection = validate(fragment, Stage::Fragment).unwrap();
+        assert_eq!(reflection.uniforms.len(), 2);
+        assert_eq!(reflection.uniforms[0].ty, "sampler2D");
+
+        let legacy = "#version 100\nprecision mediump float;\nvarying vec2 TexCoord;\nuniform sampler2D Texture;\n\
+                      void main()\n{\n    gl_FragColor = texture2D(Texture, TexCoord);\n}\n";
+        validate(legacy, Stage::Fragment).unwrap();
+
+        let block = "#version 330 core\nlayout(std140) uniform Camera {\n    mat4 view;\n};\nin vec3 aPos;\n\
+                     void main() { gl_Position = view * vec4(aPos, 1.0); }\n";
+        let reflection = validate(block, Stage::Vertex).unwrap();
+        assert_eq!(reflection.uniforms[0], Declaration { name: "Camera".into(), ty: "block".into(), location: None });
+    }
+
+    #[test]
+    fn reports_original_lines() {
+        let fragment = "#version 330 core\n\nout vec4 FragColor;\nvoid main()\n{\n    FragColor = vec4(missing);\n}\n";
+        let errors = validate(fragment, Stage::Fragment).unwrap_err();
+        assert!(errors[0].starts_with("6:"), "{:?}", errors);
+        assert!(errors[0].contains("missing"), "{:?}", errors);
+    }
+
+    #[test]
+    fn skips_comments_and_disabled_branches() {
+        let vertex = "#version 330 core\n// uniform mat4 Commented;\n/* in vec3 aOld;\n*/\nuniform mat4\n    Transform;\n\
+                      #ifdef USE_COLOR\nin vec4 aColor;\n#else\nin vec3 aPos;\n#endif\n#if __VERSION__ < 300\nattribute vec2 aLegacy;\n#endif\n\
+                      uniform sampler2D\n    Texture;\nvoid main() { gl_Position = Transform * textureLod(Texture, aPos.xy, 0.0); }\n";
+        let reflection = validate(vertex, Stage::Vertex).unwrap();
+        let attributes: Vec<&str> = reflection.attributes.iter().map(|x| x.name.as_str()).collect();
+        let uniforms: Vec<&str> = reflection.uniforms.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(attributes, vec!["aPos"]);
+        assert_eq!(uniforms, vec!["Transform", "Texture"]);
+
+        let errors = validate(&vertex.replace("aPos.xy", "missing"), Stage::Vertex).unwrap_err();
+        assert!(errors[0].starts_with("17:"), "{:?}", errors);
+    }
+
+    #[test]
+    fn rejects_unsupported_declarations() {
+        let initialized = "#version 330 core\nout vec4 FragColor;\nuniform float Scale\n    = 2.0;\nvoid main() { FragColor = vec4(Scale); }\n";
+        let errors = validate(initialized, Stage::Fragment).unwrap_err();
+        assert!(errors[0].starts_with("3:1:") && errors[0].contains("initializers"), "{:?}", errors);
+
+        let interface_block = "#version 330 core\nout Vertex {\n    vec2 uv;\n} vertex;\nvoid main() {}\n";
+        let errors = validate(interface_block, Stage::Vertex).unwrap_err();
+        assert!(errors[0].starts_with("2:1:") && errors[0].contains("blocks"), "{:?}", errors);
+
+        let unterminated = "#version 330 core\n#if defined(USE_COLOR)\nvoid main() {}\n";
+        assert!(validate(unterminated, Stage::Vertex).unwrap_err()[0].starts_with("2:1:"));
+
+        let include = "#version 330 core\n#include \"common.glsl\"\nvoid main() {}\n";
+        assert!(validate(include, Stage::Vertex).unwrap_err()[0].contains("#include"));
+    }
+
+}
@@ -0,0 +1,84 @@
+use glutin::event::{Event, StartCause, WindowEvent};
+use glutin::event_loop::ControlFlow;
+
+use log::{info, error};
+
+use ckrl::context::{Context, ContextBuilder};
+use ckrl::window::InitHints;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+
+const WINDOW_TILE: &str = "Hello viewport";
+const WINDOW_WIDTH: u32 = 800;
+const WINDOW_HEIGHT: u32 = 600;
+
+
+struct MyApp {
+    ctx: Context
+}
+
+impl MyApp {
+    fn new() -> Result<Self> {
+        info!("Creating application");
+
+        let ctx = ContextBuilder::new()
+            .with_title(WINDOW_TILE)
+            .with_size(WINDOW_WIDTH, WINDOW_HEIGHT)
+            .with_hints(InitHints::default())
+            .build()?;
+
+        Ok(Self {
+            ctx
+        })
+    }
+
+    fn run(self) {
+        let mut palette = None;
+
+        self.ctx.run(move |ctx, event, target, control_flow| {
+            *control_flow = ControlFlow::Wait;
+
+            match event {
+                Event::NewEvents(StartCause::Init) => {
+                    match ctx.create_window(target, "Palette", 300, 400) {
+                        Ok(id) => palette = Some(id),
+                        Err(err) => error!("Failed to create palette window. Cause: {}", err),
+                    }
+                }
+                Event::WindowEvent { window_id, event: WindowEvent::CloseRequested } => {
+                    if Some(window_id) == palette {
+                        ctx.close_window(window_id).unwrap();
+                        palette = None;
+                    } else {
+                        *control_flow = ControlFlow::Exit
+                    }
+                }
+                Event::RedrawRequested(window_id) => {
+                    ctx.make_current(window_id).unwrap();
+
+                    if Some(window_id) == palette {
+                        ctx.device.clear(0.3, 0.2, 0.3, 1.0);
+                    } else {
+                        ctx.device.clear(0.2, 0.3, 0.3, 1.0);
+                    }
+
+                    ctx.get_window(window_id).unwrap().swap_buffers().unwrap();
+                }
+                _ => (),
+            }
+        });
+    }
+}
+
+
+fn main() {
+
+    ckrl::start_logger();
+
+    match MyApp::new() {
+        Ok(app) => app.run(),
+        Err(err) => error!("Failed to create application. Cause: {}", err),
+    }
+
+}
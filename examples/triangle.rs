@@ -1,15 +1,17 @@
 use log::{info, error};
 
-use glutin::event::{Event, WindowEvent};
+use glutin::event::{Event, VirtualKeyCode, WindowEvent};
 use glutin::event_loop::ControlFlow;
 
 use ckrl::context::{Context, ContextBuilder};
 use ckrl::window::InitHints;
-use ckrl::gl::BufferUsage;
+use ckrl::gl::{BufferUsage, GlVersion, Primitive, Uniform};
+use ckrl::shader_string::ATTRIBUTE_POSITION;
+use ckrl::BuiltinShader;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-const WINDOW_TILE: &'static str = "Hello triangle";
+const WINDOW_TILE: &str = "Hello triangle";
 const WINDOW_WIDTH: u32 = 800;
 const WINDOW_HEIGHT: u32 = 600;
 
@@ -26,6 +28,7 @@ impl MyApp {
             .with_title(WINDOW_TILE)
             .with_size(WINDOW_WIDTH, WINDOW_HEIGHT)
             .with_hints(InitHints::default())
+            .with_gl_versions(&[GlVersion::gl(3, 3), GlVersion::gles(3, 0)])
             .build()?;
 
         Ok(Self {
@@ -33,15 +36,14 @@ impl MyApp {
         })
     }
 
-    fn run(self) {        
+    fn run(mut self) {        
         info!(
             "Pixel format of the window's GL context: {:?}",
            self.ctx.window.get_pixel_format()
         );
+        info!("GL version: {:?}", self.ctx.device.get_gl_version());
         
-        let event_loop = self.ctx.window.el;
-        let windowed_context = self.ctx.window.wc;
-        let mut device = self.ctx.device;
+        let device = &mut self.ctx.device;
 
         let vertices: [f32; 12] = [
             0.5,  0.5, 0.0,  // top right
@@ -59,14 +61,21 @@ impl MyApp {
 
         let buffer = device.new_vertex_buffer(48, 3, BufferUsage::StaticDraw).expect("Failed to create vertex buffer");
         device.set_vertex_buffer_data(&buffer, &vertices, 0);
-        device.set_vertex_buffer_attribute(&buffer, 0, 3, 0);
+        device.set_vertex_buffer_attribute(&buffer, ATTRIBUTE_POSITION, 3, 0);
 
         let index = device.new_index_buffer(24, BufferUsage::StaticDraw).expect("Failed to create index buffer");
         device.set_index_buffer_data(&index, &indices, 0);
 
-        let program = device.new_program(ckrl::VERTEX_SHADER, ckrl::FRAGMENT_SHADER).expect("Failed to create shader program");
+        let program = device.new_builtin_program(BuiltinShader::FlatColor).expect("Failed to create shader program");
+        device.set_uniform(&program, "Transform", Uniform::Mat4([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]));
+        device.set_uniform(&program, "Color", Uniform::Vec4([1.0, 0.5, 0.2, 1.0]));
 
-        event_loop.run(move |event, _, control_flow| {
+        self.ctx.run(move |ctx, event, _, control_flow| {
             //println!("{:?}", event);
             *control_flow = ControlFlow::Wait;
 
@@ -75,22 +84,20 @@ impl MyApp {
                 r = 0.0;
             }
 
-            device.clear(r, 0.3, 0.3, 1.0);
-            device.draw(&buffer, &index, &program, 6);
+            ctx.device.clear(r, 0.3, 0.3, 1.0);
+            ctx.device.draw(&buffer, &index, &program, Primitive::Triangles, 6);
+
+            if ctx.input.is_key_pressed(VirtualKeyCode::Escape) {
+                *control_flow = ControlFlow::Exit
+            }
 
             match event {
-                Event::LoopDestroyed => return,
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::Resized(physical_size) => {
-                        windowed_context.resize(physical_size)
-                    }
-                    WindowEvent::CloseRequested => {
-                        *control_flow = ControlFlow::Exit
-                    }
-                    _ => (),
-                },
+                Event::LoopDestroyed => (),
+                Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                    *control_flow = ControlFlow::Exit
+                }
                 Event::RedrawRequested(_) => {
-                    windowed_context.swap_buffers().unwrap();
+                    ctx.window.swap_buffers().unwrap();
                 }
                 _ => (),
             }
@@ -0,0 +1,90 @@
+use std::thread;
+use std::time::Duration;
+
+use glutin::event::{Event, WindowEvent};
+use glutin::event_loop::ControlFlow;
+
+use log::{info, error};
+
+use ckrl::context::{Context, ContextBuilder};
+use ckrl::window::InitHints;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+
+const WINDOW_TILE: &str = "Hello user event";
+const WINDOW_WIDTH: u32 = 800;
+const WINDOW_HEIGHT: u32 = 600;
+
+
+#[derive(Debug)]
+enum AppEvent {
+    Tick(u32),
+}
+
+struct MyApp {
+    ctx: Context<AppEvent>
+}
+
+impl MyApp {
+    fn new() -> Result<Self> {
+        info!("Creating application");
+
+        let ctx = ContextBuilder::new()
+            .with_title(WINDOW_TILE)
+            .with_size(WINDOW_WIDTH, WINDOW_HEIGHT)
+            .with_hints(InitHints::default())
+            .build_with_user_event()?;
+
+        Ok(Self {
+            ctx
+        })
+    }
+
+    fn run(self) {
+        let proxy = self.ctx.create_proxy();
+
+        thread::spawn(move || {
+            for tick in 0.. {
+                thread::sleep(Duration::from_secs(1));
+                if proxy.send_event(AppEvent::Tick(tick)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut green = 0.3;
+
+        self.ctx.run(move |ctx, event, _, control_flow| {
+            *control_flow = ControlFlow::Wait;
+
+            match event {
+                Event::UserEvent(AppEvent::Tick(tick)) => {
+                    info!("Tick {} from the worker thread", tick);
+                    green = if tick % 2 == 0 { 0.6 } else { 0.3 };
+                    ctx.window.request_redraw();
+                }
+                Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                    *control_flow = ControlFlow::Exit
+                }
+                Event::RedrawRequested(_) => {
+                    ctx.device.clear(0.2, green, 0.3, 1.0);
+                    ctx.window.swap_buffers().unwrap();
+                }
+                _ => (),
+            }
+        });
+    }
+}
+
+
+fn main() {
+
+    ckrl::start_logger();
+
+    match MyApp::new() {
+        Ok(app) => app.run(),
+        Err(err) => error!("Failed to create application. Cause: {}", err),
+    }
+
+}
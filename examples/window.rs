@@ -1,67 +1,166 @@
-use glutin::event::{Event, WindowEvent};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use glutin::event::{Event, VirtualKeyCode, WindowEvent};
 use glutin::event_loop::ControlFlow;
 
 use log::{info, error};
 
+use ckrl::command::DevConsole;
+use ckrl::console::LogConsole;
 use ckrl::context::{Context, ContextBuilder};
+use ckrl::cvar::{CvarValue, Cvars};
+use ckrl::input::TextEvent;
 use ckrl::window::InitHints;
+use ckrl::{LogBuffer, LoggerBuilder};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 
-const WINDOW_TILE: &'static str = "Hello window";
+const WINDOW_TILE: &str = "Hello window";
 const WINDOW_WIDTH: u32 = 800;
 const WINDOW_HEIGHT: u32 = 600;
+const CONFIG_FILE: &str = "window.ron";
 
 
 struct MyApp {
-    ctx: Context
+    ctx: Context,
+    console: LogConsole,
+    dev_console: DevConsole,
+    /// `r_wireframe` value left to apply at the next frame.
+    wireframe: Rc<Cell<Option<bool>>>,
 }
 
 impl MyApp {
-    fn new() -> Result<Self> {
+    fn new(log_buffer: LogBuffer) -> Result<Self> {
         info!("Creating application");
 
-        let ctx = ContextBuilder::new()
+        let mut builder = ContextBuilder::new();
+        builder
             .with_title(WINDOW_TILE)
             .with_size(WINDOW_WIDTH, WINDOW_HEIGHT)
-            .with_hints(InitHints::default())
-            .build()?;
+            .with_hints(InitHints::default());
+
+        // startup options: defaults, then the config file, then the command line
+        let mut cvars = Cvars::new();
+        if let Err(err) = cvars.load(CONFIG_FILE) {
+            info!("No config loaded: {}", err);
+        }
+        builder.register_cvars(&mut cvars);
+        cvars.register("r_wireframe", false, "Draw edges only");
+
+        let wireframe = Rc::new(Cell::new(Some(cvars.get_bool("r_wireframe"))));
+        let changed = Rc::clone(&wireframe);
+        cvars.on_change("r_wireframe", move |value| {
+            if let CvarValue::Bool(enabled) = value {
+                changed.set(Some(*enabled));
+            }
+        })?;
+
+        let mut dev_console = DevConsole::new(cvars);
+        dev_console.execute_args(std::env::args().skip(1));
+        dev_console.register_command("save", "save", |cvars, _| {
+            cvars.save(CONFIG_FILE)?;
+            Ok(format!("Saved {}", CONFIG_FILE))
+        });
+
+        let mut ctx = builder.with_cvars(dev_console.get_cvars()).build()?;
+
+        ckrl::install_crash_handler("crash.log", log_buffer.clone(), ctx.device.get_info());
+        let console = LogConsole::new(&mut ctx.device, log_buffer)?;
 
         Ok(Self {
-            ctx
+            ctx,
+            console,
+            dev_console,
+            wireframe,
         })
     }
 
-    fn run(self) {        
+    fn run(self) {
+        let mut console = self.console;
+        let mut dev_console = self.dev_console;
+        let wireframe = self.wireframe;
+        let mut history_index = 0;
+
         println!(
             "Pixel format of the window's GL context: {:?}",
            self.ctx.window.get_pixel_format()
         );
         
-        let event_loop = self.ctx.window.el;
-        let windowed_context = self.ctx.window.wc;
-        let device = self.ctx.device;
-
-        event_loop.run(move |event, _, control_flow| {
+        self.ctx.run(move |ctx, event, _, control_flow| {
             //println!("{:?}", event);
             *control_flow = ControlFlow::Wait;
 
-            device.clear(0.2, 0.3, 0.3, 1.0);
+            ctx.device.clear(0.2, 0.3, 0.3, 1.0);
 
-            match event {
-                Event::LoopDestroyed => return,
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::Resized(physical_size) => {
-                        windowed_context.resize(physical_size)
+            if ctx.input.is_key_pressed(VirtualKeyCode::Escape) {
+                *control_flow = ControlFlow::Exit
+            }
+
+            if ctx.input.is_key_pressed(VirtualKeyCode::F11) {
+                ctx.window.toggle_fullscreen();
+            }
+
+            if ctx.input.is_key_pressed(VirtualKeyCode::Grave) {
+                console.toggle();
+                if console.is_visible() {
+                    ctx.input.start_text_input();
+                } else {
+                    ctx.input.stop_text_input();
+                }
+                ctx.window.request_redraw();
+            } else if console.is_visible() {
+                for text_event in ctx.input.get_text_events() {
+                    match text_event {
+                        TextEvent::Text(text) => {
+                            let text: String = text.chars().filter(|c| !c.is_control() && *c != '`').collect();
+                            console.push_input(&text);
+                        }
+                        TextEvent::Backspace => console.pop_input(),
+                        TextEvent::Enter => {
+                            dev_console.execute_logged(&console.take_input());
+                            history_index = dev_console.get_history().len();
+                        }
+                        TextEvent::Delete => (),
                     }
-                    WindowEvent::CloseRequested => {
-                        *control_flow = ControlFlow::Exit
+                    ctx.window.request_redraw();
+                }
+
+                if ctx.input.is_key_pressed(VirtualKeyCode::Tab) {
+                    let completions = dev_console.complete(console.get_input());
+                    match completions.as_slice() {
+                        [completion] => console.set_input(&format!("{} ", completion)),
+                        [] => (),
+                        _ => info!("{}", completions.join("  ")),
                     }
-                    _ => (),
-                },
+                    ctx.window.request_redraw();
+                }
+
+                let history = dev_console.get_history();
+                if ctx.input.is_key_pressed(VirtualKeyCode::Up) && history_index > 0 {
+                    history_index -= 1;
+                    console.set_input(&history[history_index]);
+                    ctx.window.request_redraw();
+                }
+                if ctx.input.is_key_pressed(VirtualKeyCode::Down) && history_index < history.len() {
+                    history_index += 1;
+                    console.set_input(history.get(history_index).map_or("", |line| line.as_str()));
+                    ctx.window.request_redraw();
+                }
+            }
+
+            match event {
+                Event::LoopDestroyed => (),
+                Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                    *control_flow = ControlFlow::Exit
+                }
                 Event::RedrawRequested(_) => {
-                    windowed_context.swap_buffers().unwrap();
+                    if let Some(enabled) = wireframe.take() {
+                        ctx.device.set_wireframe(enabled);
+                    }
+                    console.draw(&mut ctx.device);
+                    ctx.window.swap_buffers().unwrap();
                 }
                 _ => (),
             }
@@ -72,9 +171,10 @@ impl MyApp {
 
 fn main() {
 
-    ckrl::start_logger();
+    let log_buffer = LogBuffer::new(256);
+    LoggerBuilder::new().with_buffer(log_buffer.clone()).init();
 
-    match MyApp::new() {
+    match MyApp::new(log_buffer) {
         Ok(app) => app.run(),
         Err(err) => error!("Failed to create application. Cause: {}", err),
     }
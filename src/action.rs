@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use glutin::event::{MouseButton, ScanCode, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+
+use crate::input::Input;
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    ScanCode(ScanCode),
+    Mouse(MouseButton),
+}
+
+/// Buttons that have to be held together, e.g. `[Key(LControl), Key(S)]`.
+pub type Chord = Vec<Button>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AxisSource {
+    /// -1.0 while `negative` is down, 1.0 while `positive` is down.
+    Buttons { negative: Button, positive: Button },
+    WheelX,
+    WheelY,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    pub sources: Vec<AxisSource>,
+    #[serde(default)]
+    pub dead_zone: f32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl AxisBinding {
+    pub fn new(sources: Vec<AxisSource>) -> Self {
+        Self {
+            sources,
+            dead_zone: 0.0,
+            scale: default_scale(),
+        }
+    }
+
+    pub fn with_dead_zone(mut self, dead_zone: f32) -> Self {
+        self.dead_zone = dead_zone;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+/// Named actions and axes, serialized as RON so players can rebind them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bindings {
+    #[serde(default)]
+    actions: BTreeMap<String, Vec<Chord>>,
+    #[serde(default)]
+    axes: BTreeMap<String, AxisBinding>,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ron_str(source: &str) -> Result<Bindings> {
+        Ok(ron::from_str(source)?)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Bindings> {
+        Self::from_ron_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_ron_string()?)?;
+        Ok(())
+    }
+
+    /// Adds `chord` to the chords triggering `action`.
+    pub fn bind_action<S: ToString>(&mut self, action: S, chord: Chord) -> &mut Self {
+        self.actions.entry(action.to_string()).or_default().push(chord);
+        self
+    }
+
+    /// Replaces every chord of `action` with `chord`.
+    pub fn rebind_action<S: ToString>(&mut self, action: S, chord: Chord) -> &mut Self {
+        self.actions.insert(action.to_string(), vec![chord]);
+        self
+    }
+
+    pub fn unbind_action(&mut self, action: &str) -> Option<Vec<Chord>> {
+        self.actions.remove(action)
+    }
+
+    pub fn bind_axis<S: ToString>(&mut self, axis: S, binding: AxisBinding) -> &mut Self {
+        self.axes.insert(axis.to_string(), binding);
+        self
+    }
+
+    pub fn unbind_axis(&mut self, axis: &str) -> Option<AxisBinding> {
+        self.axes.remove(axis)
+    }
+
+    pub fn get_action(&self, action: &str) -> Option<&[Chord]> {
+        self.actions.get(action).map(|chords| chords.as_slice())
+    }
+
+    pub fn get_axis(&self, axis: &str) -> Option<&AxisBinding> {
+        self.axes.get(axis)
+    }
+
+    pub fn is_action_down(&self, input: &Input, action: &str) -> bool {
+        self.chords(action).any(|chord| is_chord_down(input, chord))
+    }
+
+    /// The chord became fully held this frame.
+    pub fn is_action_pressed(&self, input: &Input, action: &str) -> bool {
+        self.chords(action).any(|chord| {
+            is_chord_down(input, chord) && chord.iter().any(|b| input.is_button_pressed(*b))
+        })
+    }
+
+    /// The chord stopped being fully held this frame.
+    pub fn is_action_released(&self, input: &Input, action: &str) -> bool {
+        self.chords(action).any(|chord| {
+            !chord.is_empty()
+                && chord.iter().any(|b| input.is_button_released(*b))
+                && chord
+                    .iter()
+                    .all(|b| input.is_button_down(*b) || input.is_button_released(*b))
+        })
+    }
+
+    /// Sum of the axis sources, zeroed inside the dead zone.
+    pub fn get_axis_value(&self, input: &Input, axis: &str) -> f32 {
+        let binding = match self.axes.get(axis) {
+            Some(binding) => binding,
+            None => return 0.0,
+        };
+
+        let value: f32 = binding
+            .sources
+            .iter()
+            .map(|source| match source {
+                AxisSource::Buttons { negative, positive } => {
+                    let mut value = 0.0;
+                    if input.is_button_down(*negative) {
+                        value -= 1.0;
+                    }
+                    if input.is_button_down(*positive) {
+                        value += 1.0;
+                    }
+                    value
+                }
+                AxisSource::WheelX => input.get_wheel_delta().0 as f32,
+                AxisSource::WheelY => input.get_wheel_delta().1 as f32,
+            })
+            .sum();
+
+        if value.abs() <= binding.dead_zone {
+            0.0
+        } else {
+            value * binding.scale
+        }
+    }
+
+    fn chords<'a>(&'a self, action: &str) -> impl Iterator<Item = &'a Chord> {
+        self.actions.get(action).into_iter().flatten()
+    }
+}
+
+fn is_chord_down(input: &Input, chord: &[Button]) -> bool {
+    !chord.is_empty() && chord.iter().all(|b| input.is_button_down(*b))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{AxisBinding, AxisSource, Bindings, Button};
+    use glutin::event::{MouseButton, VirtualKeyCode};
+
+    #[test]
+    fn bindings_round_trip_through_ron() {
+        let mut bindings = Bindings::new();
+        bindings
+            .bind_action("save", vec![Button::Key(VirtualKeyCode::LControl), Button::Key(VirtualKeyCode::S)])
+            .bind_action("fire", vec![Button::Mouse(MouseButton::Left)])
+            .bind_axis(
+                "move_x",
+                AxisBinding::new(vec![AxisSource::Buttons {
+                    negative: Button::Key(VirtualKeyCode::A),
+                    positive: Button::Key(VirtualKeyCode::D),
+                }])
+                .with_dead_zone(0.1),
+            );
+
+        let source = bindings.to_ron_string().unwrap();
+        assert_eq!(Bindings::from_ron_str(&source).unwrap(), bindings);
+    }
+
+    #[test]
+    fn axis_defaults_when_missing_from_file() {
+        let bindings = Bindings::from_ron_str("(axes: { \"zoom\": (sources: [WheelY]) })").unwrap();
+        let zoom = bindings.get_axis("zoom").unwrap();
+
+        assert_eq!(zoom.dead_zone, 0.0);
+        assert_eq!(zoom.scale, 1.0);
+    }
+
+}
@@ -0,0 +1,350 @@
+use bytemuck::Pod;
+
+use crate::reflect::ShaderInfo;
+use crate::Result;
+
+/// Memory layout of an interface block, `std140` for uniform blocks and
+/// `std430` for storage blocks unless the shader says otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLayout {
+    Std140,
+    Std430,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Uniform,
+    Storage,
+}
+
+impl From<BlockKind> for u32 {
+    fn from(kind: BlockKind) -> u32 {
+        match kind {
+            BlockKind::Uniform => glow::UNIFORM_BUFFER,
+            BlockKind::Storage => glow::SHADER_STORAGE_BUFFER,
+        }
+    }
+}
+
+/// GLSL type of a block member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlslType {
+    Float,
+    Int,
+    UInt,
+    Bool,
+    Vec2,
+    Vec3,
+    Vec4,
+    IVec2,
+    IVec3,
+    IVec4,
+    UVec2,
+    UVec3,
+    UVec4,
+    Mat2,
+    Mat3,
+    Mat4,
+    Array(&'static GlslType, usize),
+    Struct(&'static [GlslType]),
+}
+
+impl GlslType {
+    pub fn alignment(&self, layout: BlockLayout) -> usize {
+        match self {
+            GlslType::Mat2 | GlslType::Mat3 | GlslType::Mat4 => self.column().array_alignment(layout),
+            GlslType::Array(element, _) => element.array_alignment(layout),
+            GlslType::Struct(fields) => {
+                let alignment = fields.iter().map(|field| field.alignment(layout)).max().unwrap_or(4);
+                match layout {
+                    BlockLayout::Std140 => round_up(alignment, 16),
+                    BlockLayout::Std430 => alignment,
+                }
+            }
+            _ => match self.components() {
+                1 => 4,
+                2 => 8,
+                _ => 16,
+            },
+        }
+    }
+
+    pub fn size(&self, layout: BlockLayout) -> usize {
+        match self {
+            GlslType::Mat2 | GlslType::Mat3 | GlslType::Mat4 => {
+                self.components() * self.column().array_stride(layout)
+            }
+            GlslType::Array(element, count) => count * element.array_stride(layout),
+            GlslType::Struct(fields) => struct_size(layout, fields),
+            _ => 4 * self.components(),
+        }
+    }
+
+    /// Distance between two elements of an array of this type.
+    pub fn array_stride(&self, layout: BlockLayout) -> usize {
+        round_up(self.size(layout), self.array_alignment(layout))
+    }
+
+    fn array_alignment(&self, layout: BlockLayout) -> usize {
+        match layout {
+            BlockLayout::Std140 => round_up(self.alignment(layout), 16),
+            BlockLayout::Std430 => self.alignment(layout),
+        }
+    }
+
+    /// Components of vectors, columns of matrices.
+    fn components(&self) -> usize {
+        match self {
+            GlslType::Vec2 | GlslType::IVec2 | GlslType::UVec2 | GlslType::Mat2 => 2,
+            GlslType::Vec3 | GlslType::IVec3 | GlslType::UVec3 | GlslType::Mat3 => 3,
+            GlslType::Vec4 | GlslType::IVec4 | GlslType::UVec4 | GlslType::Mat4 => 4,
+            _ => 1,
+        }
+    }
+
+    fn column(&self) -> GlslType {
+        match self.components() {
+            2 => GlslType::Vec2,
+            3 => GlslType::Vec3,
+            _ => GlslType::Vec4,
+        }
+    }
+}
+
+fn round_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// Size of a struct of `fields`, a multiple of its alignment.
+pub fn struct_size(layout: BlockLayout, fields: &[GlslType]) -> usize {
+    let (_, end) = field_offsets(layout, fields);
+    let alignment = fields.iter().map(|field| field.alignment(layout)).max().unwrap_or(4);
+    match layout {
+        BlockLayout::Std140 => round_up(end, round_up(alignment, 16)),
+        BlockLayout::Std430 => round_up(end, alignment),
+    }
+}
+
+/// Offsets of consecutive members and the end of the last one.
+pub fn field_offsets(layout: BlockLayout, fields: &[GlslType]) -> (Vec<usize>, usize) {
+    let mut offset = 0;
+    let offsets = fields
+        .iter()
+        .map(|field| {
+            let start = round_up(offset, field.alignment(layout));
+            offset = start + field.size(layout);
+            start
+        })
+        .collect();
+    (offsets, offset)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockField {
+    pub name: &'static str,
+    pub ty: GlslType,
+    /// Offset of the field in the Rust struct.
+    pub offset: usize,
+}
+
+/// Rust struct mirroring a GLSL block, implemented with `block_layout!`.
+pub trait Block: Pod {
+    const LAYOUT: BlockLayout;
+
+    fn fields() -> Vec<BlockField>;
+}
+
+/// Implements `Block` for a `#[repr(C)]` struct, listing the GLSL type of
+/// each field, padding fields left out:
+///
+/// ```ignore
+/// block_layout!(Camera, Std140 { view: Mat4, position: Vec3, lights: Array(&Vec4, 4) });
+/// ```
+#[macro_export]
+macro_rules! block_layout {
+    ($name:ty, $layout:ident { $($field:ident: $ty:expr),* $(,)? }) => {
+        impl $crate::block::Block for $name {
+            const LAYOUT: $crate::block::BlockLayout = $crate::block::BlockLayout::$layout;
+
+            #[allow(unused_imports)]
+            fn fields() -> Vec<$crate::block::BlockField> {
+                use $crate::block::GlslType::*;
+                vec![$($crate::block::BlockField {
+                    name: stringify!($field),
+                    ty: $ty,
+                    offset: std::mem::offset_of!($name, $field),
+                }),*]
+            }
+        }
+    };
+}
+
+/// Checks the fields of `T` are where `T::LAYOUT` puts them and that its size
+/// is the block size, explicit padding fields included.
+pub fn check_layout<T: Block>() -> Result<()> {
+    let fields = T::fields();
+    let types: Vec<GlslType> = fields.iter().map(|field| field.ty).collect();
+    let (offsets, _) = field_offsets(T::LAYOUT, &types);
+    let name = std::any::type_name::<T>();
+
+    for (field, expected) in fields.iter().zip(offsets) {
+        if field.offset != expected {
+            return Err(failure::err_msg(format!(
+                "{}.{} is at offset {}, {:?} puts it at {}",
+                name, field.name, field.offset, T::LAYOUT, expected
+            ))
+            .into());
+        }
+    }
+
+    let size = struct_size(T::LAYOUT, &types);
+    if std::mem::size_of::<T>() != size {
+        return Err(failure::err_msg(format!(
+            "{} is {} bytes, {:?} makes it {}, add padding fields",
+            name,
+            std::mem::size_of::<T>(),
+            T::LAYOUT,
+            size
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Binding points of named blocks, applied to programs with
+/// `GraphicsDevice::apply_block_bindings` so they share buffers, e.g. a
+/// `Camera` uniform block bound to the same point in every program.
+#[derive(Debug, Clone, Default)]
+pub struct BlockBindings {
+    bindings: Vec<(String, BlockKind, u32)>,
+}
+
+impl BlockBindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_uniform_block(&mut self, name: &str, binding: u32) -> &mut Self {
+        self.with_block(name, BlockKind::Uniform, binding)
+    }
+
+    pub fn with_storage_block(&mut self, name: &str, binding: u32) -> &mut Self {
+        self.with_block(name, BlockKind::Storage, binding)
+    }
+
+    /// Gives the uniform blocks found by `include_shader!` or `shader!` the
+    /// next free binding points, blocks already known keep theirs.
+    pub fn with_reflection(&mut self, info: &ShaderInfo) -> &mut Self {
+        for uniform in info.uniforms.iter().filter(|uniform| uniform.ty == "block") {
+            if self.get_binding(uniform.name).is_none() {
+                let binding = self.next_binding(BlockKind::Uniform);
+                self.with_block(uniform.name, BlockKind::Uniform, binding);
+            }
+        }
+        self
+    }
+
+    pub fn get_binding(&self, name: &str) -> Option<(BlockKind, u32)> {
+        self.bindings
+            .iter()
+            .find(|(existing, _, _)| existing == name)
+            .map(|(_, kind, binding)| (*kind, *binding))
+    }
+
+    pub fn get_bindings(&self) -> &[(String, BlockKind, u32)] {
+        &self.bindings
+    }
+
+    fn with_block(&mut self, name: &str, kind: BlockKind, binding: u32) -> &mut Self {
+        self.bindings.retain(|(existing, _, _)| existing != name);
+        self.bindings.push((name.to_string(), kind, binding));
+        self
+    }
+
+    fn next_binding(&self, kind: BlockKind) -> u32 {
+        self.bindings
+            .iter()
+            .filter(|(_, existing, _)| *existing == kind)
+            .map(|(_, _, binding)| binding + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{check_layout, field_offsets, BlockBindings, BlockKind, BlockLayout, GlslType};
+    use crate::gl::ShaderStage;
+    use crate::reflect::{ShaderInfo, ShaderUniform};
+    use bytemuck::{Pod, Zeroable};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Camera {
+        view: [f32; 16],
+        position: [f32; 3],
+        time: f32,
+        lights: [[f32; 4]; 2],
+    }
+
+    unsafe impl Zeroable for Camera {}
+    unsafe impl Pod for Camera {}
+
+    block_layout!(Camera, Std140 { view: Mat4, position: Vec3, time: Float, lights: Array(&Vec4, 2) });
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Unpadded {
+        position: [f32; 3],
+    }
+
+    unsafe impl Zeroable for Unpadded {}
+    unsafe impl Pod for Unpadded {}
+
+    block_layout!(Unpadded, Std140 { position: Vec3 });
+
+    #[test]
+    fn computes_std140_and_std430_offsets() {
+        use GlslType::*;
+
+        let fields = [Float, Vec3, Array(&Float, 3), Mat3, Vec2];
+        assert_eq!(field_offsets(BlockLayout::Std140, &fields), (vec![0, 16, 32, 80, 128], 136));
+        assert_eq!(field_offsets(BlockLayout::Std430, &fields), (vec![0, 16, 28, 48, 96], 104));
+
+        assert_eq!(Struct(&[Vec3, Float]).size(BlockLayout::Std430), 16);
+        assert_eq!(Struct(&[Float]).size(BlockLayout::Std140), 16);
+        assert_eq!(Mat2.size(BlockLayout::Std430), 16);
+    }
+
+    #[test]
+    fn checks_struct_layouts() {
+        check_layout::<Camera>().unwrap();
+
+        let err = check_layout::<Unpadded>().unwrap_err().to_string();
+        assert!(err.contains("12 bytes") && err.contains("16"), "{}", err);
+    }
+
+    #[test]
+    fn assigns_reflected_bindings() {
+        let info = ShaderInfo {
+            source: "",
+            stage: ShaderStage::Vertex,
+            attributes: &[],
+            uniforms: &[
+                ShaderUniform { name: "Camera", ty: "block" },
+                ShaderUniform { name: "Transform", ty: "mat4" },
+                ShaderUniform { name: "Lights", ty: "block" },
+            ],
+        };
+
+        let mut bindings = BlockBindings::new();
+        bindings.with_uniform_block("Lights", 3).with_storage_block("Particles", 0).with_reflection(&info);
+        assert_eq!(bindings.get_binding("Camera"), Some((BlockKind::Uniform, 4)));
+        assert_eq!(bindings.get_binding("Lights"), Some((BlockKind::Uniform, 3)));
+        assert_eq!(bindings.get_binding("Transform"), None);
+        assert_eq!(bindings.get_bindings().len(), 3);
+    }
+
+}
@@ -0,0 +1,344 @@
+use std::collections::BTreeMap;
+
+use log::{error, info};
+
+use crate::cvar::Cvars;
+use crate::Result;
+
+type CommandFn = Box<dyn FnMut(&mut Cvars, &[&str]) -> Result<String>>;
+type CompletionFn = Box<dyn Fn(&Cvars, usize, &str) -> Vec<String>>;
+
+struct Command {
+    usage: String,
+    /// Completes the argument at index `n` from its beginning.
+    completion: Option<CompletionFn>,
+    run: CommandFn,
+}
+
+/// Runs console lines: `name` prints a cvar, `name value` sets it, anything
+/// else calls a registered command. `set`, `reset`, `toggle`, `cvarlist` and
+/// `help` are built in.
+pub struct DevConsole {
+    cvars: Cvars,
+    commands: BTreeMap<String, Command>,
+    history: Vec<String>,
+}
+
+impl DevConsole {
+    pub fn new(cvars: Cvars) -> Self {
+        let mut console = Self {
+            cvars,
+            commands: BTreeMap::new(),
+            history: Vec::new(),
+        };
+
+        console.register_command("set", "set <cvar> <value>", |cvars, args| match args {
+            [name, value] => {
+                cvars.set_from_str(name, value)?;
+                Ok(format!("{} = {}", name, cvars.get(name).unwrap()))
+            }
+            _ => Err(failure::err_msg("Usage: set <cvar> <value>").into()),
+        });
+        console.register_command("reset", "reset <cvar>", |cvars, args| match args {
+            [name] => {
+                cvars.reset(name)?;
+                Ok(format!("{} = {}", name, cvars.get(name).unwrap()))
+            }
+            _ => Err(failure::err_msg("Usage: reset <cvar>").into()),
+        });
+        console.register_command("toggle", "toggle <bool cvar>", |cvars, args| match args {
+            [name] => {
+                let value = !cvars.get_bool(name);
+                cvars.set(name, value)?;
+                Ok(format!("{} = {}", name, cvars.get(name).unwrap()))
+            }
+            _ => Err(failure::err_msg("Usage: toggle <cvar>").into()),
+        });
+        console.register_command("cvarlist", "cvarlist [prefix]", |cvars, args| {
+            let prefix = args.first().copied().unwrap_or("");
+            let lines: Vec<String> = cvars
+                .get_names()
+                .into_iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| {
+                    let cvar = cvars.get_cvar(name).unwrap();
+                    format!("{} = {} ({})", name, cvar.get_value(), cvar.get_description())
+                })
+                .collect();
+            Ok(lines.join("\n"))
+        });
+        for name in &["set", "reset", "toggle", "cvarlist"] {
+            console.set_completion(name, |cvars, index, prefix| match index {
+                0 => complete_names(cvars.get_names(), prefix),
+                _ => Vec::new(),
+            });
+        }
+
+        console
+    }
+
+    pub fn get_cvars(&self) -> &Cvars {
+        &self.cvars
+    }
+
+    pub fn get_cvars_mut(&mut self) -> &mut Cvars {
+        &mut self.cvars
+    }
+
+    /// Registers `name`, replacing a command with the same name. `run` gets
+    /// the arguments and returns the text to print.
+    pub fn register_command<F>(&mut self, name: &str, usage: &str, run: F)
+    where
+        F: 'static + FnMut(&mut Cvars, &[&str]) -> Result<String>,
+    {
+        self.commands.insert(name.to_string(), Command {
+            usage: usage.to_string(),
+            completion: None,
+            run: Box::new(run),
+        });
+    }
+
+    /// Completion of the arguments of `name`, given the argument index and its beginning.
+    pub fn set_completion<F>(&mut self, name: &str, completion: F)
+    where
+        F: 'static + Fn(&Cvars, usize, &str) -> Vec<String>,
+    {
+        if let Some(command) = self.commands.get_mut(name) {
+            command.completion = Some(Box::new(completion));
+        }
+    }
+
+    pub fn get_command_names(&self) -> Vec<&str> {
+        self.commands.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Runs `line`, returning what to print.
+    pub fn execute(&mut self, line: &str) -> Result<String> {
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            return Ok(String::new());
+        }
+        if self.history.last().map(|last| last.as_str()) != Some(line.trim()) {
+            self.history.push(line.trim().to_string());
+        }
+
+        let name = tokens[0].as_str();
+        let args: Vec<&str> = tokens[1..].iter().map(|arg| arg.as_str()).collect();
+
+        if name == "help" {
+            return Ok(self.help());
+        }
+
+        if let Some(command) = self.commands.get_mut(name) {
+            return (command.run)(&mut self.cvars, &args);
+        }
+
+        match (self.cvars.get_cvar(name), args.as_slice()) {
+            (Some(cvar), []) => Ok(format!(
+                "{} = {} (default {}) - {}",
+                name,
+                cvar.get_value(),
+                cvar.get_default(),
+                cvar.get_description()
+            )),
+            (Some(_), [value]) => {
+                self.cvars.set_from_str(name, value)?;
+                Ok(format!("{} = {}", name, self.cvars.get(name).unwrap()))
+            }
+            (Some(_), _) => Err(failure::err_msg(format!("Usage: {} <value>", name)).into()),
+            (None, _) => Err(failure::err_msg(format!("Unknown command or cvar {}", name)).into()),
+        }
+    }
+
+    /// Same as `execute`, logging the result instead of returning it.
+    pub fn execute_logged(&mut self, line: &str) {
+        info!("> {}", line);
+        match self.execute(line) {
+            Ok(output) => {
+                for output_line in output.lines() {
+                    info!("{}", output_line);
+                }
+            }
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    /// Runs the lines given on the command line, each starting with a `+`,
+    /// e.g. `+set r_vsync 0 +r_width 1280`. Other arguments are ignored.
+    pub fn execute_args<I, S>(&mut self, args: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for line in split_args(args) {
+            self.execute_logged(&line);
+        }
+    }
+
+    /// Lines `line` may complete to, sorted.
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let tokens = tokenize(line);
+        let ends_with_space = line.ends_with(' ');
+
+        if tokens.is_empty() || (tokens.len() == 1 && !ends_with_space) {
+            let prefix = tokens.first().map(|token| token.as_str()).unwrap_or("");
+            let mut names = self.get_command_names();
+            names.push("help");
+            names.extend(self.cvars.get_names());
+            let mut lines = complete_names(names, prefix);
+            lines.dedup();
+            return lines;
+        }
+
+        let (index, prefix) = if ends_with_space {
+            (tokens.len() - 1, "")
+        } else {
+            (tokens.len() - 2, tokens[tokens.len() - 1].as_str())
+        };
+
+        let candidates = match self.commands.get(&tokens[0]).and_then(|command| command.completion.as_ref()) {
+            Some(completion) => completion(&self.cvars, index, prefix),
+            None => Vec::new(),
+        };
+
+        let start: Vec<String> = tokens[..index + 1].iter().map(|token| quote(token)).collect();
+        let start = start.join(" ");
+        candidates.into_iter().map(|candidate| format!("{} {}", start, quote(&candidate))).collect()
+    }
+
+    /// Lines run so far, oldest first.
+    pub fn get_history(&self) -> &[String] {
+        &self.history
+    }
+
+    fn help(&self) -> String {
+        let mut lines: Vec<String> = self.commands.values().map(|command| command.usage.clone()).collect();
+        lines.push("<cvar> [value]".into());
+        lines.join("\n")
+    }
+}
+
+fn complete_names(names: Vec<&str>, prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Splits on whitespace, double quotes grouping words.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut has_token = false;
+
+    for character in line.chars() {
+        match character {
+            '"' => {
+                quoted = !quoted;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut token));
+                    has_token = false;
+                }
+            }
+            c => {
+                token.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Quotes `token` when `tokenize` would split it or drop it.
+fn quote(token: &str) -> String {
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        format!("\"{}\"", token)
+    } else {
+        token.to_string()
+    }
+}
+
+fn split_args<I, S>(args: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut lines: Vec<Vec<String>> = Vec::new();
+
+    for arg in args {
+        let arg = arg.as_ref();
+        if let Some(start) = arg.strip_prefix('+') {
+            lines.push(vec![start.to_string()]);
+        } else if let Some(line) = lines.last_mut() {
+            line.push(quote(arg));
+        }
+    }
+
+    lines.into_iter().map(|line| line.join(" ")).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{split_args, tokenize, DevConsole};
+    use crate::cvar::Cvars;
+
+    fn console() -> DevConsole {
+        let mut cvars = Cvars::new();
+        cvars.register("r_vsync", true, "Wait for the vertical blank");
+        cvars.register("r_wireframe", false, "Draw edges only");
+        cvars.register("name", "player", "Player name");
+        DevConsole::new(cvars)
+    }
+
+    #[test]
+    fn runs_cvars_and_commands() {
+        let mut console = console();
+        console.register_command("echo", "echo <text>", |_, args| Ok(args.join(" ")));
+
+        assert_eq!(console.execute("echo hello  \"big world\"").unwrap(), "hello big world");
+        assert_eq!(console.execute("r_vsync 0").unwrap(), "r_vsync = 0");
+        assert_eq!(console.execute("toggle r_wireframe").unwrap(), "r_wireframe = 1");
+        assert_eq!(console.execute("set name \"some one\"").unwrap(), "name = some one");
+        assert!(console.execute("r_vsync").unwrap().starts_with("r_vsync = 0 (default 1)"));
+        assert!(console.execute("set r_vsync").is_err());
+        assert!(console.execute("nope").is_err());
+        assert_eq!(console.get_history().len(), 7);
+    }
+
+    #[test]
+    fn completes_names_and_arguments() {
+        let mut console = console();
+
+        assert_eq!(console.complete("r_"), vec!["r_vsync", "r_wireframe"]);
+        assert_eq!(console.complete("to"), vec!["toggle"]);
+        assert_eq!(console.complete("toggle r_w"), vec!["toggle r_wireframe"]);
+        assert_eq!(console.complete("set ").len(), 3);
+        assert!(console.complete("set r_vsync ").is_empty());
+
+        console.register_command("give", "give <who> <item>", |_, _| Ok(String::new()));
+        console.set_completion("give", |_, index, _| vec![index.to_string()]);
+        assert_eq!(console.complete("give \"some one\" "), vec!["give \"some one\" 1"]);
+    }
+
+    #[test]
+    fn parses_lines_and_args() {
+        assert_eq!(tokenize("set name \"\""), vec!["set", "name", ""]);
+        assert_eq!(
+            split_args(["game", "+set", "r_vsync", "0", "+name", "some one"]),
+            vec!["set r_vsync 0", "name \"some one\""]
+        );
+    }
+
+}
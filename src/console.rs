@@ -0,0 +1,416 @@
+use log::{Level, LevelFilter};
+
+use crate::gl::{
+    BufferUsage, FilterMode, GraphicsDevice, Primitive, RawIndexBuffer, RawProgram,
+    RawTexture, RawVertexArray, RawVertexBuffer, Uniform,
+};
+use crate::logger::{LogBuffer, LogRecord};
+use crate::shader_string::{BuiltinShader, ATTRIBUTE_COLOR, ATTRIBUTE_POSITION, ATTRIBUTE_TEX_COORD};
+use crate::Result;
+
+const GLYPH_SIZE: usize = 8;
+const ATLAS_COLUMNS: usize = 16;
+/// 128 ASCII glyphs then one opaque cell for the background.
+const ATLAS_ROWS: usize = 9;
+const SOLID_CELL: usize = 128;
+/// pos2 uv2 color4
+const VERTEX_SIZE: usize = 8;
+const MAX_QUADS: usize = 8192;
+/// Positions are computed in clip space.
+const IDENTITY: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+];
+
+/// Drop-down overlay showing the records of a `LogBuffer`, drawn over the
+/// whole viewport with an embedded 8x8 font.
+///
+/// It owns a vertex array where supported, so it has to be drawn in the GL
+/// context it was created in.
+pub struct LogConsole {
+    buffer: LogBuffer,
+    visible: bool,
+    level: LevelFilter,
+    filter: String,
+    scale: f32,
+    height: f32,
+    scroll: usize,
+    background: (f32, f32, f32, f32),
+    /// Line being typed, shown under the records.
+    input: String,
+    atlas: RawTexture,
+    /// `None` below GL 3.0 and GLES 3.0, the attributes are then specified
+    /// again on each draw.
+    vertex_array: Option<RawVertexArray>,
+    vertex_buffer: RawVertexBuffer,
+    index_buffer: RawIndexBuffer,
+    program: RawProgram,
+    vertices: Vec<f32>,
+}
+
+impl LogConsole {
+    pub fn new(device: &mut GraphicsDevice, buffer: LogBuffer) -> Result<LogConsole> {
+        let atlas = device.new_texture(
+            (ATLAS_COLUMNS * GLYPH_SIZE) as i32,
+            (ATLAS_ROWS * GLYPH_SIZE) as i32,
+            FilterMode::Nearest,
+        )?;
+        device.set_texture_data(&atlas, &font_atlas());
+
+        let program = device.new_builtin_program(BuiltinShader::TexturedTint)?;
+        device.set_uniform(&program, "Transform", Uniform::Mat4(IDENTITY));
+
+        let vertex_array = if device.get_gl_version().supports_vertex_arrays() {
+            Some(device.new_vertex_array()?)
+        } else {
+            None
+        };
+        device.bind_vertex_array(vertex_array.as_ref());
+
+        let vertex_buffer = device.new_vertex_buffer(
+            MAX_QUADS * 4 * VERTEX_SIZE * std::mem::size_of::<f32>(),
+            VERTEX_SIZE,
+            BufferUsage::DynamicDraw,
+        )?;
+        set_attributes(device, &vertex_buffer);
+
+        let index_buffer = device.new_index_buffer(MAX_QUADS * 6, BufferUsage::StaticDraw)?;
+        device.set_index_buffer_data(&index_buffer, &quad_indices(MAX_QUADS), 0);
+        device.bind_vertex_array(None);
+
+        Ok(LogConsole {
+            buffer,
+            visible: false,
+            level: LevelFilter::Trace,
+            filter: String::new(),
+            scale: 2.0,
+            height: 0.5,
+            scroll: 0,
+            background: (0.0, 0.0, 0.0, 0.75),
+            input: String::new(),
+            atlas,
+            vertex_array,
+            vertex_buffer,
+            index_buffer,
+            program,
+            vertices: Vec::new(),
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Hides the records less severe than `level`.
+    pub fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+        self.scroll = 0;
+    }
+
+    pub fn get_level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Only shows the records whose message or target contains `filter`,
+    /// ignoring case. Empty shows everything.
+    pub fn set_filter(&mut self, filter: &str) {
+        self.filter = filter.to_lowercase();
+        self.scroll = 0;
+    }
+
+    pub fn get_filter(&self) -> &str {
+        &self.filter
+    }
+
+    /// Size of a glyph in pixels is `8 * scale`, 2 by default.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(1.0);
+    }
+
+    /// Part of the viewport covered, from the top, 0.5 by default.
+    pub fn set_height(&mut self, height: f32) {
+        self.height = height.clamp(0.0, 1.0);
+    }
+
+    pub fn set_background(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.background = (r, g, b, a);
+    }
+
+    /// Moves back in the history by `lines`, negative to move forward.
+    pub fn scroll(&mut self, lines: i32) {
+        let scroll = self.scroll as i64 + lines as i64;
+        self.scroll = scroll.max(0) as usize;
+    }
+
+    pub fn get_input(&self) -> &str {
+        &self.input
+    }
+
+    /// Replaces the typed line, e.g. with a completion or a history entry.
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_string();
+    }
+
+    pub fn push_input(&mut self, text: &str) {
+        self.input.push_str(text);
+    }
+
+    pub fn pop_input(&mut self) {
+        self.input.pop();
+    }
+
+    /// Clears the typed line, returning it to be run.
+    pub fn take_input(&mut self) -> String {
+        self.scroll = 0;
+        std::mem::take(&mut self.input)
+    }
+
+    pub fn get_records(&self) -> Vec<LogRecord> {
+        filter_records(self.buffer.get_records(), self.level, &self.filter)
+    }
+
+    /// Draws over the current viewport, does nothing while hidden.
+    pub fn draw(&mut self, device: &mut GraphicsDevice) {
+        if !self.visible {
+            return;
+        }
+
+        let (_, _, width, height) = device.get_viewport();
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        let quads = self.build_vertices(width as f32, height as f32);
+        if quads == 0 {
+            return;
+        }
+
+        let blending = device.get_alpha_blending();
+        device.set_alpha_blending(true);
+        device.bind_vertex_array(self.vertex_array.as_ref());
+        if self.vertex_array.is_none() {
+            set_attributes(device, &self.vertex_buffer);
+        }
+        device.set_vertex_buffer_data(&self.vertex_buffer, &self.vertices, 0);
+        device.bind_texture(0, Some(&self.atlas));
+        device.draw(&self.vertex_buffer, &self.index_buffer, &self.program, Primitive::Triangles, (quads * 6) as i32);
+        device.bind_vertex_array(None);
+        device.set_alpha_blending(blending);
+    }
+
+    fn build_vertices(&mut self, width: f32, height: f32) -> usize {
+        self.vertices.clear();
+
+        let glyph = GLYPH_SIZE as f32 * self.scale;
+        let console_height = (height * self.height / glyph).floor() * glyph;
+        let lines = (console_height / glyph) as usize;
+        let columns = (width / glyph) as usize;
+        if lines < 2 || columns == 0 {
+            return 0;
+        }
+        // the last line holds the input
+        let record_lines = lines - 1;
+
+        let (r, g, b, a) = self.background;
+        let mut quads = 0;
+        let mut push_quad = |vertices: &mut Vec<f32>, rect: (f32, f32, f32, f32), cell: usize, color: [f32; 4]| {
+            if quads == MAX_QUADS {
+                return;
+            }
+            let (x, y, w, h) = rect;
+            let (u0, v0, u1, v1) = cell_uv(cell);
+            let (left, top) = to_clip(x, y, width, height);
+            let (right, bottom) = to_clip(x + w, y + h, width, height);
+
+            for &(px, py, u, v) in &[(left, top, u0, v0), (right, top, u1, v0), (right, bottom, u1, v1), (left, bottom, u0, v1)] {
+                vertices.extend_from_slice(&[px, py, u, v]);
+                vertices.extend_from_slice(&color);
+            }
+            quads += 1;
+        };
+
+        push_quad(&mut self.vertices, (0.0, 0.0, width, console_height), SOLID_CELL, [r, g, b, a]);
+
+        let records = self.get_records();
+        // the newest record sits right above the input
+        self.scroll = self.scroll.min(records.len().saturating_sub(record_lines));
+        let end = records.len() - self.scroll;
+        let start = end.saturating_sub(record_lines);
+
+        let mut text_lines = vec![(String::new(), [0.0; 4]); record_lines - (end - start)];
+        text_lines.extend(
+            records[start..end]
+                .iter()
+                .map(|record| (record.to_line(), level_color(record.level))),
+        );
+        // keep the end of a long input visible
+        let prompt = format!("> {}_", self.input);
+        let skip = prompt.chars().count().saturating_sub(columns);
+        text_lines.push((prompt.chars().skip(skip).collect(), [1.0; 4]));
+
+        for (line, (text, color)) in text_lines.iter().enumerate() {
+            let y = line as f32 * glyph;
+
+            for (column, character) in text.chars().take(columns).enumerate() {
+                let code = if character.is_ascii() { character as usize } else { b'?' as usize };
+                if code == b' ' as usize {
+                    continue;
+                }
+                push_quad(&mut self.vertices, (column as f32 * glyph, y, glyph, glyph), code, *color);
+            }
+        }
+
+        quads
+    }
+}
+
+fn set_attributes(device: &mut GraphicsDevice, vertex_buffer: &RawVertexBuffer) {
+    device.set_vertex_buffer_attribute(vertex_buffer, ATTRIBUTE_POSITION, 2, 0);
+    device.set_vertex_buffer_attribute(vertex_buffer, ATTRIBUTE_TEX_COORD, 2, 2);
+    device.set_vertex_buffer_attribute(vertex_buffer, ATTRIBUTE_COLOR, 4, 4);
+}
+
+fn filter_records(records: Vec<LogRecord>, level: LevelFilter, filter: &str) -> Vec<LogRecord> {
+    records
+        .into_iter()
+        .filter(|record| record.level <= level)
+        .filter(|record| {
+            filter.is_empty()
+                || record.message.to_lowercase().contains(filter)
+                || record.target.to_lowercase().contains(filter)
+        })
+        .collect()
+}
+
+fn level_color(level: Level) -> [f32; 4] {
+    match level {
+        Level::Error => [1.0, 0.3, 0.3, 1.0],
+        Level::Warn => [1.0, 0.85, 0.3, 1.0],
+        Level::Info => [0.9, 0.9, 0.9, 1.0],
+        Level::Debug => [0.4, 0.8, 1.0, 1.0],
+        Level::Trace => [0.6, 0.6, 0.6, 1.0],
+    }
+}
+
+/// Pixel position, origin at the top-left corner, to clip space.
+fn to_clip(x: f32, y: f32, width: f32, height: f32) -> (f32, f32) {
+    (x / width * 2.0 - 1.0, 1.0 - y / height * 2.0)
+}
+
+fn cell_uv(cell: usize) -> (f32, f32, f32, f32) {
+    let column = (cell % ATLAS_COLUMNS) as f32;
+    let row = (cell / ATLAS_COLUMNS) as f32;
+    let (columns, rows) = (ATLAS_COLUMNS as f32, ATLAS_ROWS as f32);
+
+    if cell == SOLID_CELL {
+        // sample the middle of the cell, clear of any filtering at the edges
+        let (u, v) = ((column + 0.5) / columns, (row + 0.5) / rows);
+        return (u, v, u, v);
+    }
+
+    (column / columns, row / rows, (column + 1.0) / columns, (row + 1.0) / rows)
+}
+
+/// RGBA8 atlas, white glyphs on transparent cells, first row at the top.
+fn font_atlas() -> Vec<u8> {
+    let width = ATLAS_COLUMNS * GLYPH_SIZE;
+    let mut data = vec![0; width * ATLAS_ROWS * GLYPH_SIZE * 4];
+
+    let mut set_pixel = |x: usize, y: usize| {
+        let index = (y * width + x) * 4;
+        data[index..index + 4].copy_from_slice(&[255, 255, 255, 255]);
+    };
+
+    for (code, glyph) in font8x8::legacy::BASIC_LEGACY.iter().enumerate() {
+        let (cell_x, cell_y) = ((code % ATLAS_COLUMNS) * GLYPH_SIZE, (code / ATLAS_COLUMNS) * GLYPH_SIZE);
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_SIZE {
+                // the lowest bit is the leftmost pixel
+                if row & (1 << x) != 0 {
+                    set_pixel(cell_x + x, cell_y + y);
+                }
+            }
+        }
+    }
+
+    let (cell_x, cell_y) = ((SOLID_CELL % ATLAS_COLUMNS) * GLYPH_SIZE, (SOLID_CELL / ATLAS_COLUMNS) * GLYPH_SIZE);
+    for y in 0..GLYPH_SIZE {
+        for x in 0..GLYPH_SIZE {
+            set_pixel(cell_x + x, cell_y + y);
+        }
+    }
+
+    data
+}
+
+fn quad_indices(quads: usize) -> Vec<u32> {
+    (0..quads as u32)
+        .flat_map(|quad| {
+            let first = quad * 4;
+            vec![first, first + 1, first + 2, first + 2, first + 3, first]
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{filter_records, font_atlas, ATLAS_COLUMNS, GLYPH_SIZE};
+    use crate::logger::LogRecord;
+    use log::{Level, LevelFilter};
+
+    fn record(level: Level, message: &str) -> LogRecord {
+        LogRecord {
+            level,
+            target: "ckrl::gl".into(),
+            message: message.into(),
+            timestamp: "00:00:00.000".into(),
+            thread: "main".into(),
+        }
+    }
+
+    #[test]
+    fn filters_by_level_and_text() {
+        let records = vec![
+            record(Level::Error, "Shader failed"),
+            record(Level::Debug, "shader compiled"),
+            record(Level::Warn, "Slow frame"),
+        ];
+
+        let messages = |records: Vec<LogRecord>| records.into_iter().map(|x| x.message).collect::<Vec<_>>();
+
+        assert_eq!(
+            messages(filter_records(records.clone(), LevelFilter::Warn, "")),
+            vec!["Shader failed", "Slow frame"]
+        );
+        assert_eq!(
+            messages(filter_records(records.clone(), LevelFilter::Trace, "shader")),
+            vec!["Shader failed", "shader compiled"]
+        );
+        assert_eq!(filter_records(records, LevelFilter::Trace, "ckrl::gl").len(), 3);
+    }
+
+    #[test]
+    fn atlas_holds_glyphs_and_solid_cell() {
+        let atlas = font_atlas();
+        let width = ATLAS_COLUMNS * GLYPH_SIZE;
+        let alpha = |x: usize, y: usize| atlas[(y * width + x) * 4 + 3];
+
+        // space is empty, the solid cell starts the ninth row
+        let (space_x, space_y) = ((32 % ATLAS_COLUMNS) * GLYPH_SIZE, (32 / ATLAS_COLUMNS) * GLYPH_SIZE);
+        assert!((0..GLYPH_SIZE).all(|i| alpha(space_x + i, space_y + i) == 0));
+        assert_eq!(alpha(3, 8 * GLYPH_SIZE + 3), 255);
+    }
+
+}
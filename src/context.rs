@@ -1,23 +1,285 @@
+use std::collections::HashMap;
+
+use glutin::dpi::PhysicalSize;
+use glutin::event::{Event, WindowEvent};
+use glutin::event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopWindowTarget};
+use glutin::window::WindowId;
+use log::{error, info, warn};
+
 use crate::window::{Window, InitHints};
 use crate::Result;
-use crate::gl::GraphicsDevice;
+use crate::cvar::Cvars;
+use crate::monitor::FullscreenMode;
+use crate::gl::{GlVersion, GraphicsDevice};
+use crate::input::Input;
 
-pub struct Context {
+/// `E` is the type of the user events sent through `create_proxy`.
+pub struct Context<E: 'static = ()> {
     pub device: GraphicsDevice,
     pub window: Window,
+    /// Input of the primary window.
+    pub input: Input,
+    scale_factor: f64,
+    /// Applied to the windows of `create_window` too.
+    hints: InitHints,
+    /// Windows created with `create_window`, `window` being the primary one.
+    windows: HashMap<WindowId, Window>,
+    /// Input of each window of `windows`.
+    inputs: HashMap<WindowId, Input>,
+    current_window: WindowId,
+    event_loop: Option<EventLoop<E>>,
+    /// Kept to hand out proxies once the event loop runs.
+    proxy: EventLoopProxy<E>,
 }
 
-impl Context {
-    fn new(settings: &ContextBuilder) -> Result<Context> {
-        let (window, gl_context) = Window::new(settings)?;
-        let device = GraphicsDevice::new(gl_context)?;
+impl<E: 'static> Context<E> {
+    fn new(settings: &ContextBuilder) -> Result<Context<E>> {
+        let event_loop = EventLoop::with_user_event();
+        let (window, gl_context) = Window::new(settings, &event_loop)?;
+        let mut device = GraphicsDevice::new(gl_context)?;
+        device.load_extra_functions(|name| window.get_windowed_context().get_proc_address(name) as *const _);
+        let scale_factor = window.get_windowed_context().window().scale_factor();
+        let input = Input::new(scale_factor);
+
+        let size = window.get_windowed_context().window().inner_size();
+        device.set_viewport(0, 0, size.width as i32, size.height as i32);
+
+        let current_window = window.id();
+        let proxy = event_loop.create_proxy();
 
         Ok(Self {
             device,
             window,
+            input,
+            scale_factor,
+            hints: settings.platform_hints.clone(),
+            windows: HashMap::new(),
+            inputs: HashMap::new(),
+            current_window,
+            event_loop: Some(event_loop),
+            proxy,
         })
         
     }
+
+    pub fn get_scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Size of the framebuffer, in physical pixels.
+    pub fn get_physical_size(&self) -> (u32, u32) {
+        let size = self.window.get_windowed_context().window().inner_size();
+        (size.width, size.height)
+    }
+
+    pub fn get_logical_size(&self) -> (f64, f64) {
+        let size = self.window.get_windowed_context().window().inner_size().to_logical(self.scale_factor);
+        (size.width, size.height)
+    }
+
+    /// Column-major orthographic projection mapping logical pixels, origin at
+    /// the top-left corner, to clip space.
+    pub fn get_projection(&self) -> [f32; 16] {
+        let (width, height) = self.get_logical_size();
+        let (width, height) = (width as f32, height as f32);
+
+        [
+            2.0 / width, 0.0, 0.0, 0.0,
+            0.0, -2.0 / height, 0.0, 0.0,
+            0.0, 0.0, -1.0, 0.0,
+            -1.0, 1.0, 0.0, 1.0,
+        ]
+    }
+
+    /// Handle to wake the event loop from another thread, the events sent come
+    /// back as `Event::UserEvent` in the `run` callback. Also available from
+    /// inside that callback.
+    pub fn create_proxy(&self) -> EventLoopProxy<E> {
+        self.proxy.clone()
+    }
+
+    /// Opens a window whose GL context shares buffers, textures and programs
+    /// with the primary one. Vertex attributes have to be set again once the
+    /// new window is current, each context having its own vertex array. The
+    /// window gets its own `Input`, see `get_input`.
+    pub fn create_window(
+        &mut self,
+        target: &EventLoopWindowTarget<E>,
+        title: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<WindowId> {
+        let mut window = Window::new_shared(
+            target,
+            &self.window,
+            self.device.get_gl_version(),
+            &self.hints,
+            title,
+            width,
+            height,
+        )?;
+
+        // the new GL context is current from here
+        window.context_id = self.device.add_context()?;
+        let id = window.id();
+        let size = window.get_windowed_context().window().inner_size();
+        self.device.set_viewport(0, 0, size.width as i32, size.height as i32);
+
+        let scale_factor = window.get_windowed_context().window().scale_factor();
+        self.inputs.insert(id, Input::new(scale_factor));
+        self.windows.insert(id, window);
+        self.current_window = id;
+        info!("Created window {:?}", id);
+        Ok(id)
+    }
+
+    /// Closes a window opened with `create_window`, the primary window stays
+    /// and becomes current.
+    pub fn close_window(&mut self, id: WindowId) -> Result<()> {
+        let context_id = match self.windows.get(&id) {
+            Some(window) => window.context_id,
+            None => return Ok(()),
+        };
+
+        // the context has to be current to delete its vertex array
+        self.make_current(id)?;
+        self.device.remove_context(context_id, self.window.context_id)?;
+        self.window.make_current()?;
+        self.current_window = self.window.id();
+
+        self.windows.remove(&id);
+        self.inputs.remove(&id);
+        Ok(())
+    }
+
+    pub fn get_window(&self, id: WindowId) -> Option<&Window> {
+        if id == self.window.id() {
+            Some(&self.window)
+        } else {
+            self.windows.get(&id)
+        }
+    }
+
+    /// Input of window `id`, `input` for the primary window.
+    pub fn get_input(&self, id: WindowId) -> Option<&Input> {
+        if id == self.window.id() {
+            Some(&self.input)
+        } else {
+            self.inputs.get(&id)
+        }
+    }
+
+    pub fn get_input_mut(&mut self, id: WindowId) -> Option<&mut Input> {
+        if id == self.window.id() {
+            Some(&mut self.input)
+        } else {
+            self.inputs.get_mut(&id)
+        }
+    }
+
+    pub fn get_window_ids(&self) -> Vec<WindowId> {
+        let mut ids = vec![self.window.id()];
+        ids.extend(self.windows.keys());
+        ids
+    }
+
+    pub fn get_current_window(&self) -> WindowId {
+        self.current_window
+    }
+
+    /// Directs the device to the GL context of window `id`.
+    pub fn make_current(&mut self, id: WindowId) -> Result<()> {
+        if id == self.current_window {
+            return Ok(());
+        }
+
+        let window = if id == self.window.id() {
+            &mut self.window
+        } else {
+            self.windows
+                .get_mut(&id)
+                .ok_or_else(|| failure::err_msg(format!("Unknown window {:?}", id)))?
+        };
+
+        window.make_current()?;
+        self.device.set_current_context(window.context_id)?;
+        self.current_window = id;
+        Ok(())
+    }
+
+    /// Keeps the context in sync with the window before user code sees the event.
+    fn handle_event(&mut self, event: &Event<E>) {
+        // window events go to the input of their window, the others to all
+        match event {
+            Event::WindowEvent { window_id, .. } => {
+                if let Some(input) = self.get_input_mut(*window_id) {
+                    input.handle_event(event);
+                }
+            }
+            _ => {
+                self.input.handle_event(event);
+                for input in self.inputs.values_mut() {
+                    input.handle_event(event);
+                }
+            }
+        }
+
+        if let Event::WindowEvent { window_id, event } = event {
+            let result = match event {
+                WindowEvent::Resized(size) => self.resize(*window_id, *size),
+                WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                    if *window_id == self.window.id() {
+                        self.scale_factor = *scale_factor;
+                    }
+                    self.resize(*window_id, **new_inner_size)
+                }
+                _ => Ok(()),
+            };
+
+            if let Err(err) = result {
+                error!("Failed to resize window {:?}: {}", window_id, err);
+            }
+        }
+    }
+
+    fn resize(&mut self, id: WindowId, size: PhysicalSize<u32>) -> Result<()> {
+        if self.get_window(id).is_none() {
+            return Ok(());
+        }
+
+        let current = self.current_window;
+        self.make_current(id)?;
+        if let Some(window) = self.get_window(id) {
+            window.get_windowed_context().resize(size);
+        }
+        self.device.set_viewport(0, 0, size.width as i32, size.height as i32);
+        self.make_current(current)
+    }
+
+    /// Runs the event loop, keeping the context up to date before each event
+    /// is handed to `callback`. The window target is needed by `create_window`.
+    pub fn run<F>(mut self, mut callback: F) -> !
+    where
+        F: 'static + FnMut(&mut Context<E>, Event<E>, &EventLoopWindowTarget<E>, &mut ControlFlow),
+    {
+        let event_loop = self.event_loop.take().expect("Event loop already running");
+
+        event_loop.run(move |event, target, control_flow| {
+            self.handle_event(&event);
+            callback(&mut self, event, target, control_flow);
+        })
+    }
+}
+
+impl<E: 'static> Drop for Context<E> {
+    fn drop(&mut self) {
+        let ids: Vec<WindowId> = self.windows.keys().cloned().collect();
+        for id in ids {
+            if let Err(err) = self.close_window(id) {
+                warn!("Failed to close window {:?}: {}", id, err);
+            }
+        }
+    }
 }
 
 
@@ -27,6 +289,7 @@ pub struct ContextBuilder {
     pub(crate) window_width: u32,
     pub(crate) window_height: u32,
     pub(crate) platform_hints: InitHints,
+    pub(crate) gl_versions: Vec<GlVersion>,
 }
 
 impl ContextBuilder {
@@ -57,9 +320,73 @@ impl ContextBuilder {
         self
     }
 
+    /// Acceptable GL versions by order of preference, replacing
+    /// `InitHints::gl_version` when not empty.
+    pub fn with_gl_versions(&mut self, gl_versions: &[GlVersion]) -> &mut Self {
+        self.gl_versions = gl_versions.to_vec();
+        self
+    }
+
+    /// Registers the startup options as cvars, with the current settings as
+    /// defaults: `r_width`, `r_height`, `r_vsync`, `r_srgb`, `r_monitor` (-1
+    /// for the primary one) and `r_fullscreen` (0 windowed, 1 borderless, 2
+    /// exclusive at `r_width`x`r_height`). They take effect on the next start.
+    pub fn register_cvars(&self, cvars: &mut Cvars) {
+        let hints = &self.platform_hints;
+        let fullscreen = match hints.fullscreen {
+            FullscreenMode::Windowed => 0,
+            FullscreenMode::Borderless => 1,
+            FullscreenMode::Exclusive { .. } => 2,
+        };
+
+        cvars.register("r_width", self.window_width as i64, "Window width");
+        cvars.register("r_height", self.window_height as i64, "Window height");
+        cvars.register("r_vsync", hints.vsync, "Wait for the vertical blank");
+        cvars.register("r_srgb", hints.srgb, "sRGB framebuffer");
+        cvars.register("r_monitor", hints.monitor.map_or(-1, |index| index as i64), "Fullscreen monitor, -1 for the primary one");
+        cvars.register("r_fullscreen", fullscreen, "0 windowed, 1 borderless, 2 exclusive");
+    }
+
+    /// Overrides the settings with the cvars set by `register_cvars`, e.g.
+    /// after loading a config file and running the command line.
+    pub fn with_cvars(&mut self, cvars: &Cvars) -> &mut Self {
+        if let Some(width) = cvars.get_int("r_width") {
+            self.window_width = width.clamp(1, u32::MAX as i64) as u32;
+        }
+        if let Some(height) = cvars.get_int("r_height") {
+            self.window_height = height.clamp(1, u32::MAX as i64) as u32;
+        }
+        if cvars.contains("r_vsync") {
+            self.platform_hints.vsync = cvars.get_bool("r_vsync");
+        }
+        if cvars.contains("r_srgb") {
+            self.platform_hints.srgb = cvars.get_bool("r_srgb");
+        }
+        if let Some(monitor) = cvars.get_int("r_monitor") {
+            self.platform_hints.monitor = if monitor < 0 { None } else { Some(monitor as usize) };
+        }
+        if let Some(fullscreen) = cvars.get_int("r_fullscreen") {
+            self.platform_hints.fullscreen = match fullscreen {
+                1 => FullscreenMode::Borderless,
+                2 => FullscreenMode::Exclusive {
+                    width: self.window_width,
+                    height: self.window_height,
+                    refresh_rate: None,
+                },
+                _ => FullscreenMode::Windowed,
+            };
+        }
+        self
+    }
+
     pub fn build(&self) -> Result<Context> {
         Context::new(self)
     }
+
+    /// Builds a context whose event loop carries user events of type `E`.
+    pub fn build_with_user_event<E: 'static>(&self) -> Result<Context<E>> {
+        Context::new(self)
+    }
 }
 
 
@@ -70,6 +397,43 @@ impl Default for ContextBuilder {
             window_width: 800,
             window_height: 600,
             platform_hints: InitHints::default(),
+            gl_versions: Vec::new(),
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::ContextBuilder;
+    use crate::cvar::Cvars;
+    use crate::monitor::FullscreenMode;
+
+    #[test]
+    fn cvars_override_startup_options() {
+        let mut builder = ContextBuilder::new();
+        let mut cvars = Cvars::new();
+        builder.register_cvars(&mut cvars);
+        assert_eq!(cvars.get_int("r_width"), Some(800));
+
+        cvars.set("r_width", 1920).unwrap();
+        cvars.set("r_height", 1080).unwrap();
+        cvars.set("r_vsync", false).unwrap();
+        cvars.set("r_fullscreen", 2).unwrap();
+        builder.with_cvars(&cvars);
+
+        assert_eq!((builder.window_width, builder.window_height), (1920, 1080));
+        assert!(!builder.platform_hints.vsync);
+        assert_eq!(
+            builder.platform_hints.fullscreen,
+            FullscreenMode::Exclusive { width: 1920, height: 1080, refresh_rate: None }
+        );
+
+        cvars.set("r_width", i64::MAX).unwrap();
+        cvars.set("r_height", -1).unwrap();
+        builder.with_cvars(&cvars);
+        assert_eq!((builder.window_width, builder.window_height), (u32::MAX, 1));
+    }
+
+}
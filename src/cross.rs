@@ -0,0 +1,200 @@
+use naga::back::glsl;
+use naga::front::{spv, wgsl};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+use crate::gl::{ShaderError, ShaderSource, ShaderStage};
+use crate::shader_string::ShaderDialect;
+use crate::Result;
+
+/// GLSL written by naga for one stage.
+///
+/// GLSL 3.30 and ES 3.00 have no `layout(binding = n)`, so the WGSL or SPIR-V
+/// `@binding` numbers are returned here and applied after linking. Groups are
+/// ignored, bindings have to be unique across them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossCompiled {
+    pub source: String,
+    /// Uniform block names and their binding points.
+    pub uniform_blocks: Vec<(String, u32)>,
+    /// Sampler uniform names and their texture units, the `@binding` of the
+    /// texture: bind it with `GraphicsDevice::bind_texture(unit, ..)`.
+    pub samplers: Vec<(String, u32)>,
+}
+
+/// Translates `source` to the GLSL of `dialect`, GLSL sources being returned as is.
+///
+/// The output keeps the clip space of the source: WGSL depth goes from 0 to 1,
+/// so projections have to be built for that range.
+pub fn translate(source: &ShaderSource, stage: ShaderStage, dialect: ShaderDialect) -> Result<CrossCompiled> {
+    let compile_error = |log: String| -> Box<dyn std::error::Error> { ShaderError::Compile { stage, log }.into() };
+
+    let (module, entry_point, text) = match *source {
+        ShaderSource::Glsl(source) => {
+            return Ok(CrossCompiled {
+                source: source.to_string(),
+                uniform_blocks: Vec::new(),
+                samplers: Vec::new(),
+            })
+        }
+        ShaderSource::Wgsl { source, entry_point } => {
+            let module = wgsl::parse_str(source).map_err(|err| compile_error(err.emit_to_string(source)))?;
+            (module, entry_point, Some(source))
+        }
+        ShaderSource::SpirV { data, entry_point } => {
+            let module = spv::parse_u8_slice(data, &spv::Options::default())
+                .map_err(|err| compile_error(err.to_string()))?;
+            (module, entry_point, None)
+        }
+    };
+
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|err| match text {
+            Some(text) => compile_error(err.emit_to_string(text)),
+            None => compile_error(err.to_string()),
+        })?;
+
+    let version = match dialect {
+        ShaderDialect::Glsl330 => glsl::Version::Desktop(330),
+        ShaderDialect::GlslEs300 => glsl::Version::new_gles(300),
+        ShaderDialect::Glsl140 | ShaderDialect::Glsl120 | ShaderDialect::GlslEs100 => {
+            return Err(failure::err_msg(format!(
+                "Translated shaders need GLSL 3.30 or ES 3.00, the context has {:?}",
+                dialect
+            ))
+            .into())
+        }
+    };
+
+    let mut binding_map = glsl::BindingMap::default();
+    for (_, variable) in module.global_variables.iter() {
+        if let Some(binding) = &variable.binding {
+            if binding.binding > u8::MAX as u32 {
+                return Err(compile_error(format!("Binding {} is above {}", binding.binding, u8::MAX)));
+            }
+            binding_map.insert(*binding, binding.binding as u8);
+        }
+    }
+
+    let options = glsl::Options {
+        version,
+        writer_flags: glsl::WriterFlags::empty(),
+        binding_map,
+        zero_initialize_workgroup_memory: true,
+    };
+    let shader_stage = match stage {
+        ShaderStage::Vertex => naga::ShaderStage::Vertex,
+        ShaderStage::Fragment => naga::ShaderStage::Fragment,
+        ShaderStage::Compute => naga::ShaderStage::Compute,
+        _ => return Err(compile_error(format!("naga can't translate {:?} shaders", stage))),
+    };
+    let pipeline_options = glsl::PipelineOptions {
+        shader_stage,
+        entry_point: entry_point.to_string(),
+        multiview: None,
+    };
+
+    let mut output = String::new();
+    let reflection = glsl::Writer::new(
+        &mut output,
+        &module,
+        &info,
+        &options,
+        &pipeline_options,
+        naga::proc::BoundsCheckPolicies::default(),
+    )
+    .and_then(|mut writer| writer.write())
+    .map_err(|err| compile_error(err.to_string()))?;
+
+    let binding_of = |handle: naga::Handle<naga::GlobalVariable>| {
+        module.global_variables[handle].binding.as_ref().map(|binding| binding.binding)
+    };
+
+    let mut uniform_blocks: Vec<(String, u32)> = reflection
+        .uniforms
+        .iter()
+        .filter(|(handle, _)| module.global_variables[**handle].space == naga::AddressSpace::Uniform)
+        .filter_map(|(handle, name)| Some((name.clone(), binding_of(*handle)?)))
+        .collect();
+    let mut samplers: Vec<(String, u32)> = reflection
+        .texture_mapping
+        .iter()
+        .filter_map(|(name, mapping)| Some((name.clone(), binding_of(mapping.texture)?)))
+        .collect();
+    uniform_blocks.sort();
+    samplers.sort();
+
+    Ok(CrossCompiled {
+        source: output,
+        uniform_blocks,
+        samplers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::translate;
+    use crate::gl::{ShaderError, ShaderSource, ShaderStage};
+    use crate::shader_string::ShaderDialect;
+
+    static SPRITE: &str = r#"
+struct Camera {
+    transform: mat4x4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(0) @binding(1) var sprite_texture: texture_2d<f32>;
+@group(0) @binding(2) var sprite_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) tex_coord: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera.transform * vec4<f32>(position, 1.0);
+    out.tex_coord = tex_coord;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sprite_texture, sprite_sampler, in.tex_coord);
+}
+"#;
+
+    #[test]
+    fn translates_wgsl_with_bindings() {
+        let vertex = ShaderSource::Wgsl { source: SPRITE, entry_point: "vs_main" };
+        let compiled = translate(&vertex, ShaderStage::Vertex, ShaderDialect::Glsl330).unwrap();
+        assert!(compiled.source.starts_with("#version 330 core"));
+        assert_eq!(compiled.uniform_blocks.len(), 1);
+        assert_eq!(compiled.uniform_blocks[0].1, 0);
+        assert!(compiled.samplers.is_empty());
+
+        let fragment = ShaderSource::Wgsl { source: SPRITE, entry_point: "fs_main" };
+        let compiled = translate(&fragment, ShaderStage::Fragment, ShaderDialect::GlslEs300).unwrap();
+        assert!(compiled.source.starts_with("#version 300 es"));
+        assert_eq!(compiled.samplers.len(), 1);
+        assert_eq!(compiled.samplers[0].1, 1);
+
+        assert!(translate(&fragment, ShaderStage::Fragment, ShaderDialect::Glsl120).is_err());
+    }
+
+    #[test]
+    fn reports_wgsl_errors_as_compile_errors() {
+        let source = ShaderSource::Wgsl {
+            source: "@vertex fn vs_main() -> @builtin(position) vec4<f32> { return missing; }",
+            entry_point: "vs_main",
+        };
+        let err = translate(&source, ShaderStage::Vertex, ShaderDialect::Glsl330).unwrap_err();
+        match err.downcast_ref::<ShaderError>() {
+            Some(ShaderError::Compile { stage: ShaderStage::Vertex, log }) => assert!(log.contains("missing"), "{}", log),
+            _ => panic!("{}", err),
+        }
+    }
+
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CvarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl CvarValue {
+    /// Parses `text` as a value of the same type as `self`. Booleans also
+    /// accept `0`, `1`, `on` and `off`.
+    pub fn parse_as(&self, text: &str) -> Result<CvarValue> {
+        let invalid = || failure::err_msg(format!("Invalid value {:?}, expected {}", text, self.type_name()));
+
+        match self {
+            CvarValue::Bool(_) => match text.to_lowercase().as_str() {
+                "1" | "true" | "on" => Ok(CvarValue::Bool(true)),
+                "0" | "false" | "off" => Ok(CvarValue::Bool(false)),
+                _ => Err(invalid().into()),
+            },
+            CvarValue::Int(_) => text.parse().map(CvarValue::Int).map_err(|_| invalid().into()),
+            CvarValue::Float(_) => text.parse().map(CvarValue::Float).map_err(|_| invalid().into()),
+            CvarValue::Str(_) => Ok(CvarValue::Str(text.to_string())),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CvarValue::Bool(_) => "bool",
+            CvarValue::Int(_) => "int",
+            CvarValue::Float(_) => "float",
+            CvarValue::Str(_) => "string",
+        }
+    }
+
+    fn same_type(&self, other: &CvarValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CvarValue::Bool(value) => write!(f, "{}", if *value { 1 } else { 0 }),
+            CvarValue::Int(value) => write!(f, "{}", value),
+            CvarValue::Float(value) => write!(f, "{}", value),
+            CvarValue::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<bool> for CvarValue {
+    fn from(value: bool) -> Self {
+        CvarValue::Bool(value)
+    }
+}
+
+impl From<i64> for CvarValue {
+    fn from(value: i64) -> Self {
+        CvarValue::Int(value)
+    }
+}
+
+impl From<i32> for CvarValue {
+    fn from(value: i32) -> Self {
+        CvarValue::Int(value.into())
+    }
+}
+
+impl From<f64> for CvarValue {
+    fn from(value: f64) -> Self {
+        CvarValue::Float(value)
+    }
+}
+
+impl From<&str> for CvarValue {
+    fn from(value: &str) -> Self {
+        CvarValue::Str(value.to_string())
+    }
+}
+
+type ChangeCallback = Box<dyn FnMut(&CvarValue)>;
+
+pub struct Cvar {
+    value: CvarValue,
+    default: CvarValue,
+    description: String,
+    /// Saved by `Cvars::save`.
+    archive: bool,
+    callback: Option<ChangeCallback>,
+}
+
+impl Cvar {
+    pub fn get_value(&self) -> &CvarValue {
+        &self.value
+    }
+
+    pub fn get_default(&self) -> &CvarValue {
+        &self.default
+    }
+
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archive
+    }
+}
+
+/// Typed console variables, e.g. `r_vsync`, set from code, the developer
+/// console, the command line or a config file.
+#[derive(Default)]
+pub struct Cvars {
+    vars: BTreeMap<String, Cvar>,
+    /// Values loaded before their cvar was registered.
+    pending: BTreeMap<String, CvarValue>,
+}
+
+impl Cvars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `name`, archived by default. A value loaded or set earlier
+    /// for `name` replaces `default` when it has the same type.
+    pub fn register<V>(&mut self, name: &str, default: V, description: &str) -> &mut Cvar
+    where
+        V: Into<CvarValue>,
+    {
+        let default = default.into();
+        let value = match self.pending.remove(name) {
+            Some(value) if value.same_type(&default) => value,
+            Some(value) => {
+                warn!("Ignoring {} value {:?} for {} cvar {}", value.type_name(), value, default.type_name(), name);
+                default.clone()
+            }
+            None => default.clone(),
+        };
+
+        self.vars.insert(name.to_string(), Cvar {
+            value,
+            default,
+            description: description.to_string(),
+            archive: true,
+            callback: None,
+        });
+        self.vars.get_mut(name).unwrap()
+    }
+
+    /// Called with the new value each time `name` changes.
+    pub fn on_change<F>(&mut self, name: &str, callback: F) -> Result<()>
+    where
+        F: 'static + FnMut(&CvarValue),
+    {
+        let cvar = self.get_cvar_mut(name)?;
+        cvar.callback = Some(Box::new(callback));
+        Ok(())
+    }
+
+    /// Whether `save` writes `name`.
+    pub fn set_archive(&mut self, name: &str, archive: bool) -> Result<()> {
+        self.get_cvar_mut(name)?.archive = archive;
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.vars.contains_key(name)
+    }
+
+    pub fn get_cvar(&self, name: &str) -> Option<&Cvar> {
+        self.vars.get(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CvarValue> {
+        self.vars.get(name).map(|cvar| &cvar.value)
+    }
+
+    /// Value of a bool cvar, false when missing or of another type.
+    pub fn get_bool(&self, name: &str) -> bool {
+        matches!(self.get(name), Some(CvarValue::Bool(true)))
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(CvarValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name) {
+            Some(CvarValue::Float(value)) => Some(*value),
+            Some(CvarValue::Int(value)) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(CvarValue::Str(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets `name`, failing if the type differs from the registered one.
+    pub fn set<V>(&mut self, name: &str, value: V) -> Result<()>
+    where
+        V: Into<CvarValue>,
+    {
+        let value = value.into();
+        let cvar = self.get_cvar_mut(name)?;
+        if !cvar.value.same_type(&value) {
+            return Err(failure::err_msg(format!(
+                "{} is a {} cvar, got a {}",
+                name,
+                cvar.value.type_name(),
+                value.type_name()
+            ))
+            .into());
+        }
+
+        if cvar.value != value {
+            cvar.value = value;
+            if let Some(callback) = &mut cvar.callback {
+                callback(&cvar.value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `name` from text, as typed in the console.
+    pub fn set_from_str(&mut self, name: &str, text: &str) -> Result<()> {
+        let value = self.get_cvar_mut(name)?.value.parse_as(text)?;
+        self.set(name, value)
+    }
+
+    pub fn reset(&mut self, name: &str) -> Result<()> {
+        let default = self.get_cvar_mut(name)?.default.clone();
+        self.set(name, default)
+    }
+
+    pub fn get_names(&self) -> Vec<&str> {
+        self.vars.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Archived values in RON.
+    pub fn to_ron_string(&self) -> Result<String> {
+        let values: BTreeMap<&String, &CvarValue> = self
+            .vars
+            .iter()
+            .filter(|(_, cvar)| cvar.archive)
+            .map(|(name, cvar)| (name, &cvar.value))
+            .collect();
+        Ok(ron::ser::to_string_pretty(&values, ron::ser::PrettyConfig::default())?)
+    }
+
+    /// Applies values written by `to_ron_string`. Unknown names are kept
+    /// until registered.
+    pub fn apply_ron_str(&mut self, text: &str) -> Result<()> {
+        let values: BTreeMap<String, CvarValue> = ron::de::from_str(text)?;
+
+        for (name, value) in values {
+            if self.contains(&name) {
+                if let Err(err) = self.set(&name, value) {
+                    warn!("Ignoring config value: {}", err);
+                }
+            } else {
+                self.pending.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        info!("Loading cvars from {:?}", path.as_ref());
+        self.apply_ron_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        info!("Saving cvars to {:?}", path.as_ref());
+        fs::write(path, self.to_ron_string()?)?;
+        Ok(())
+    }
+
+    fn get_cvar_mut(&mut self, name: &str) -> Result<&mut Cvar> {
+        self.vars
+            .get_mut(name)
+            .ok_or_else(|| failure::err_msg(format!("Unknown cvar {}", name)).into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{CvarValue, Cvars};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn set_checks_type_and_calls_back() {
+        let mut cvars = Cvars::new();
+        cvars.register("r_vsync", true, "Wait for the vertical blank");
+
+        let changes = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&changes);
+        cvars.on_change("r_vsync", move |_| counter.set(counter.get() + 1)).unwrap();
+
+        cvars.set_from_str("r_vsync", "off").unwrap();
+        cvars.set("r_vsync", false).unwrap();
+        assert!(!cvars.get_bool("r_vsync"));
+        assert_eq!(changes.get(), 1);
+
+        assert!(cvars.set("r_vsync", 1).is_err());
+        assert!(cvars.set_from_str("r_vsync", "maybe").is_err());
+        assert!(cvars.set("r_missing", true).is_err());
+
+        cvars.reset("r_vsync").unwrap();
+        assert_eq!(cvars.get("r_vsync"), Some(&CvarValue::Bool(true)));
+        assert_eq!(changes.get(), 2);
+    }
+
+    #[test]
+    fn config_round_trip_and_pending_values() {
+        let mut cvars = Cvars::new();
+        cvars.register("r_width", 800, "");
+        cvars.register("name", "player", "");
+        cvars.register("session", 0, "").archive = false;
+        cvars.set("r_width", 1280).unwrap();
+
+        let text = cvars.to_ron_string().unwrap();
+        assert!(!text.contains("session"));
+
+        let mut loaded = Cvars::new();
+        loaded.apply_ron_str(&text).unwrap();
+        assert_eq!(loaded.get("r_width"), None);
+
+        loaded.register("r_width", 800, "");
+        loaded.register("name", 0.5, "");
+        assert_eq!(loaded.get_int("r_width"), Some(1280));
+        assert_eq!(loaded.get_float("name"), Some(0.5));
+    }
+
+}
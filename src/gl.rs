@@ -1,39 +1,290 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
 use std::mem;
+use std::ptr;
 use std::rc::Rc;
 use glow::{Context as GlowContext, HasContext};
 
-use log::{info, error, debug};
+use log::{info, warn, error, debug, trace};
 
 use crate::Result;
+use crate::block::{check_layout, Block, BlockBindings, BlockKind, BlockLayout};
+use crate::stream::{StreamAllocator, StreamKind, StreamMode, StreamSlice, STREAM_FRAMES};
+use crate::shader_string::{BuiltinShader, ShaderDialect, ATTRIBUTES};
+use crate::preprocessor::{ProcessedShader, ShaderPreprocessor};
+use crate::program_cache::{cast_function, ProgramBinaryFunctions, ProgramCache};
 
 type BufferId = <GlowContext as HasContext>::Buffer;
 type ProgramId = <GlowContext as HasContext>::Program;
+type ShaderId = <GlowContext as HasContext>::Shader;
 type VertexArrayId = <GlowContext as HasContext>::VertexArray;
+type TextureId = <GlowContext as HasContext>::Texture;
+type FramebufferId = <GlowContext as HasContext>::Framebuffer;
+type FenceId = <GlowContext as HasContext>::Fence;
 
+/// Handles dropped since the binding caches were last updated, GL reusing
+/// the names of deleted objects.
+type DeletedHandles = Rc<RefCell<Vec<DeletedHandle>>>;
+
+#[derive(Debug, Clone, Copy)]
+enum DeletedHandle {
+    Buffer(BufferId),
+    Program(ProgramId),
+    Texture(TextureId),
+    VertexArray(VertexArrayId),
+    Framebuffer(FramebufferId),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlApi {
+    OpenGl,
+    OpenGlEs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlVersion {
+    pub api: GlApi,
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl GlVersion {
+    pub const fn gl(major: u8, minor: u8) -> Self {
+        Self { api: GlApi::OpenGl, major, minor }
+    }
+
+    pub const fn gles(major: u8, minor: u8) -> Self {
+        Self { api: GlApi::OpenGlEs, major, minor }
+    }
+
+    /// Parses a `GL_VERSION` string, e.g. "4.6.0 NVIDIA 440.44" or "OpenGL ES 3.0 Mesa".
+    pub fn parse(version: &str) -> Option<GlVersion> {
+        let (api, numbers) = match version.find("OpenGL ES") {
+            Some(index) => (GlApi::OpenGlEs, &version[index + "OpenGL ES".len()..]),
+            None => (GlApi::OpenGl, version),
+        };
+
+        let numbers = numbers.trim_start_matches(|c: char| !c.is_ascii_digit());
+        let mut parts = numbers.split(|c: char| !c.is_ascii_digit());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+
+        Some(GlVersion { api, major, minor })
+    }
+
+    /// Vertex arrays need GL 3.0 or GLES 3.0, without them the attributes
+    /// are context state.
+    pub fn supports_vertex_arrays(&self) -> bool {
+        self.major >= 3
+    }
+
+    /// Separate read and draw framebuffers, and blitting between them, need
+    /// GL 3.0 or GLES 3.0.
+    pub fn supports_framebuffer_blit(&self) -> bool {
+        self.major >= 3
+    }
+
+    /// Geometry shaders need GL 3.2 or GLES 3.2, tessellation GL 4.0 or GLES
+    /// 3.2 and compute GL 4.3 or GLES 3.1.
+    pub fn supports_stage(&self, stage: ShaderStage) -> bool {
+        let version = (self.major, self.minor);
+        match (self.api, stage) {
+            (_, ShaderStage::Vertex) | (_, ShaderStage::Fragment) => true,
+            (GlApi::OpenGl, ShaderStage::Geometry) => version >= (3, 2),
+            (GlApi::OpenGl, ShaderStage::TessControl) | (GlApi::OpenGl, ShaderStage::TessEvaluation) => version >= (4, 0),
+            (GlApi::OpenGl, ShaderStage::Compute) => version >= (4, 3),
+            (GlApi::OpenGlEs, ShaderStage::Compute) => version >= (3, 1),
+            (GlApi::OpenGlEs, _) => version >= (3, 2),
+        }
+    }
+
+    /// Uniform blocks need GL 3.1 or GLES 3.0, storage blocks GL 4.3 or GLES 3.1.
+    pub fn supports_block(&self, kind: BlockKind) -> bool {
+        let version = (self.major, self.minor);
+        match (self.api, kind) {
+            (GlApi::OpenGl, BlockKind::Uniform) => version >= (3, 1),
+            (GlApi::OpenGlEs, BlockKind::Uniform) => version >= (3, 0),
+            (_, BlockKind::Storage) => self.supports_stage(ShaderStage::Compute),
+        }
+    }
+
+    /// Checks the stages of a graphics program: a vertex stage, no compute
+    /// one, no duplicates, tessellation control only with evaluation.
+    pub fn check_stages(&self, stages: &[ShaderStage]) -> Result<()> {
+        for (index, stage) in stages.iter().enumerate() {
+            if *stage == ShaderStage::Compute {
+                return Err(failure::err_msg("Compute shaders go in their own program, see new_compute_program").into());
+            }
+            if stages[..index].contains(stage) {
+                return Err(failure::err_msg(format!("Program has two {:?} shaders", stage)).into());
+            }
+            if !self.supports_stage(*stage) {
+                return Err(failure::err_msg(format!("{:?} shaders are not supported by {:?}", stage, self)).into());
+            }
+        }
+        if !stages.contains(&ShaderStage::Vertex) {
+            return Err(failure::err_msg("Program has no vertex shader").into());
+        }
+        if stages.contains(&ShaderStage::TessControl) && !stages.contains(&ShaderStage::TessEvaluation) {
+            return Err(failure::err_msg("Tessellation control shader without an evaluation one").into());
+        }
+        Ok(())
+    }
+}
 
 pub struct GraphicsDevice {
+    /// Raw GL bypasses the binding cache, prefer `with_raw_gl`.
     pub gl: Rc<GlowContext>,
+    gl_version: GlVersion,
+    cache: BindingCache,
+    context_id: usize,
+    next_context_id: usize,
+    /// Caches of the other GL contexts, see `add_context`.
+    other_caches: HashMap<usize, BindingCache>,
+    /// See `load_extra_functions`.
+    extra_functions: ExtraFunctions,
+    /// See `set_state_validation`.
+    state_validation: bool,
+    /// Shared with the handles, see `forget_deleted`.
+    deleted: DeletedHandles,
+    /// See `supports_buffer_storage`, queried once as it lists the extensions.
+    buffer_storage: bool,
+}
+
+/// Functions glow doesn't expose, loaded by `load_extra_functions`.
+#[derive(Default)]
+struct ExtraFunctions {
+    program_binary: Option<ProgramBinaryFunctions>,
+    memory_barrier: Option<unsafe extern "system" fn(u32)>,
+}
+
+/// Bindings of one GL context, vertex arrays and framebuffers aren't shared
+/// between contexts so each one gets its own.
+struct BindingCache {
     current_vertex_buffer: Option<BufferId>,
     current_index_buffer: Option<BufferId>,
     current_program: Option<ProgramId>,
     current_vertex_array: Option<VertexArrayId>,
+    current_framebuffer: Option<FramebufferId>,
+    active_texture_unit: u32,
+    /// Texture bound on each unit, units missing here being unknown.
+    textures: HashMap<u32, Option<TextureId>>,
+    /// Index buffer bound in each vertex array but the current one.
+    index_buffers: HashMap<VertexArrayId, BufferId>,
+    /// Vertex array bound when no `RawVertexArray` is, None without vertex arrays.
+    default_vertex_array: Option<VertexArrayId>,
+    viewport: (i32, i32, i32, i32),
+    alpha_blending: bool,
+    wireframe: bool,
+    /// `GL_PATCH_VERTICES` last set by a draw, None when unknown.
+    patch_vertices: Option<u32>,
+}
+
+impl BindingCache {
+    /// Creates the vertex array of the current GL context when `gl_version` has them.
+    unsafe fn new(gl: &GlowContext, gl_version: GlVersion) -> Result<BindingCache> {
+        let current_vertex_array = if gl_version.supports_vertex_arrays() {
+            let vertex_array = gl.create_vertex_array()?;
+            gl.bind_vertex_array(Some(vertex_array));
+            Some(vertex_array)
+        } else {
+            None
+        };
+
+        Ok(BindingCache {
+            current_vertex_buffer: None,
+            current_index_buffer: None,
+            current_program: None,
+            current_vertex_array,
+            current_framebuffer: None,
+            active_texture_unit: 0,
+            textures: HashMap::new(),
+            index_buffers: HashMap::new(),
+            default_vertex_array: current_vertex_array,
+            viewport: (0, 0, 0, 0),
+            alpha_blending: false,
+            wireframe: false,
+            patch_vertices: None,
+        })
+    }
+
+    /// Drops the bindings of a deleted object, so a new object getting the
+    /// same name is bound again.
+    fn forget(&mut self, handle: DeletedHandle) {
+        match handle {
+            DeletedHandle::Buffer(id) => {
+                if self.current_vertex_buffer == Some(id) {
+                    self.current_vertex_buffer = None;
+                }
+                if self.current_index_buffer == Some(id) {
+                    self.current_index_buffer = None;
+                }
+                self.index_buffers.retain(|_, index_buffer| *index_buffer != id);
+            }
+            DeletedHandle::Program(id) => {
+                if self.current_program == Some(id) {
+                    self.current_program = None;
+                }
+            }
+            DeletedHandle::Texture(id) => {
+                self.textures.retain(|_, texture| *texture != Some(id));
+            }
+            DeletedHandle::VertexArray(id) => {
+                self.index_buffers.remove(&id);
+                if self.current_vertex_array == Some(id) {
+                    self.current_vertex_array = None;
+                    self.current_index_buffer = None;
+                }
+            }
+            DeletedHandle::Framebuffer(id) => {
+                if self.current_framebuffer == Some(id) {
+                    self.current_framebuffer = None;
+                }
+            }
+        }
+    }
 }
 
 
 impl GraphicsDevice {
+    /// Builds a device on a GL context created outside of ckrl (SDL2, another
+    /// glutin, an editor host...), the context must be current on this thread.
+    pub fn from_loader_function<F>(mut loader: F) -> Result<GraphicsDevice>
+    where
+        F: FnMut(&str) -> *const std::os::raw::c_void,
+    {
+        let mut device = Self::new(GlowContext::from_loader_function(|name| loader(name)))?;
+        device.load_extra_functions(loader);
+        Ok(device)
+    }
+
     pub fn new(gl: GlowContext) -> Result<GraphicsDevice> {
         unsafe {
-            let current_vertex_array = gl.create_vertex_array()?;
-            gl.bind_vertex_array(Some(current_vertex_array));
+            let version = gl.get_parameter_string(glow::VERSION);
+            let gl_version = GlVersion::parse(&version)
+                .ok_or_else(|| failure::err_msg(format!("Unknown GL version {:?}", version)))?;
+            info!("Using {:?}", gl_version);
+
+            let cache = BindingCache::new(&gl, gl_version)?;
 
-            Ok(GraphicsDevice {
+            let mut device = GraphicsDevice {
                 gl: Rc::new(gl),
+                gl_version,
 
-                current_vertex_buffer: None,
-                current_index_buffer: None,
-                current_program: None,
-                current_vertex_array: Some(current_vertex_array),
-            })
+                cache,
+                context_id: 0,
+                next_context_id: 1,
+                other_caches: HashMap::new(),
+                extra_functions: ExtraFunctions::default(),
+                state_validation: cfg!(debug_assertions),
+                deleted: DeletedHandles::default(),
+                buffer_storage: false,
+            };
+            device.buffer_storage = device.query_buffer_storage();
+            Ok(device)
         }
     }
 
@@ -53,6 +304,79 @@ impl GraphicsDevice {
         unsafe { self.gl.get_parameter_string(glow::SHADING_LANGUAGE_VERSION) }
     }
 
+    pub fn get_extensions(&self) -> Vec<String> {
+        unsafe {
+            if self.gl_version.major >= 3 {
+                let count = self.gl.get_parameter_i32(glow::NUM_EXTENSIONS);
+                (0..count.max(0) as u32)
+                    .map(|index| self.gl.get_parameter_indexed_string(glow::EXTENSIONS, index))
+                    .collect()
+            } else {
+                let extensions = self.gl.get_parameter_string(glow::EXTENSIONS);
+                extensions.split_whitespace().map(String::from).collect()
+            }
+        }
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.get_extensions().iter().any(|extension| extension == name)
+    }
+
+    /// Loads the functions glow lacks: program binaries for `new_cached_program`
+    /// and `glMemoryBarrier`. Done by `from_loader_function` and `Context`, call
+    /// it with the loader of the context given to `new`.
+    pub fn load_extra_functions<F>(&mut self, mut loader: F)
+    where
+        F: FnMut(&str) -> *const std::os::raw::c_void,
+    {
+        if self.supports_stage(ShaderStage::Compute) {
+            self.extra_functions.memory_barrier = unsafe { cast_function(loader("glMemoryBarrier")) };
+        }
+
+        let version = (self.gl_version.major, self.gl_version.minor);
+        let supported = match self.gl_version.api {
+            GlApi::OpenGl => version >= (4, 1) || self.has_extension("GL_ARB_get_program_binary"),
+            GlApi::OpenGlEs => version >= (3, 0) || self.has_extension("GL_OES_get_program_binary"),
+        };
+        // some drivers support the functions without any binary format
+        let formats = if supported {
+            unsafe { self.gl.get_parameter_i32(glow::NUM_PROGRAM_BINARY_FORMATS) }
+        } else {
+            0
+        };
+
+        self.extra_functions.program_binary = if formats > 0 {
+            ProgramBinaryFunctions::load(loader)
+        } else {
+            None
+        };
+        info!("Program binaries supported: {}", self.supports_program_binary());
+    }
+
+    pub fn supports_program_binary(&self) -> bool {
+        self.extra_functions.program_binary.is_some()
+    }
+
+    pub fn get_gl_version(&self) -> GlVersion {
+        self.gl_version
+    }
+
+    /// Header of `get_shader_dialect`, for sources written without one.
+    pub fn get_glsl_header(&self) -> String {
+        self.get_shader_dialect().get_header()
+    }
+
+    pub fn set_viewport(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        if self.cache.viewport != (x, y, width, height) {
+            unsafe { self.gl.viewport(x, y, width, height) }
+            self.cache.viewport = (x, y, width, height);
+        }
+    }
+
+    pub fn get_viewport(&self) -> (i32, i32, i32, i32) {
+        self.cache.viewport
+    }
+
     pub fn clear(&self, r: f32, g: f32, b: f32, a: f32) {
         unsafe {
             self.gl.clear_color(r, g, b, a);
@@ -65,20 +389,30 @@ impl GraphicsDevice {
         vertex_buffer: &RawVertexBuffer,
         index_buffer: &RawIndexBuffer,
         program: &RawProgram,
+        primitive: Primitive,
         count: i32,
         ) {
+        if !self.set_patch_vertices(primitive) {
+            return;
+        }
+
         unsafe {
-            //self.gl.bind_vertex_array(self.current_vertex_array);
-            self.bind_vertex_buffer(Some(&vertex_buffer));
-            self.bind_index_buffer(Some(&index_buffer));
+            //self.gl.bind_vertex_array(self.cache.current_vertex_array);
+            self.bind_vertex_buffer(Some(vertex_buffer));
+            self.bind_index_buffer(Some(index_buffer));
             self.bind_program(Some(program));
+
+            if self.state_validation {
+                self.validate_state_cache();
+            }
+
             //self.gl.draw_arrays(
             //    glow::TRIANGLES,
             //    0,
             //    count,
             //);
             self.gl.draw_elements(
-                 glow::TRIANGLES, 
+                 primitive.into(),
                  count, 
                  glow::UNSIGNED_INT,
                  0
@@ -99,6 +433,7 @@ impl GraphicsDevice {
 
             let buffer = RawVertexBuffer {
                 gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
                 id,
                 count,
                 stride,
@@ -125,8 +460,8 @@ impl GraphicsDevice {
         offset: usize,
     ) {
         unsafe {    
-            info!("Set vertex buffer data");
-            self.bind_vertex_buffer(Some(&buffer));
+            trace!("Set vertex buffer data");
+            self.bind_vertex_buffer(Some(buffer));
 
             let u8_buffer = bytemuck::cast_slice(data);
         
@@ -136,7 +471,7 @@ impl GraphicsDevice {
                 u8_buffer
             );
 
-            debug!("Vertex data copied in buffer with glGetError {}", self.gl.get_error());
+            trace!("Vertex data copied in buffer with glGetError {}", self.gl.get_error());
         }
     }
 
@@ -148,7 +483,7 @@ impl GraphicsDevice {
         offset: usize,
     ) {
         unsafe {
-            info!("Set vertex buffer attribute");
+            trace!("Set vertex buffer attribute");
             self.bind_vertex_buffer(Some(buffer));
 
             self.gl.vertex_attrib_pointer_f32(
@@ -161,7 +496,7 @@ impl GraphicsDevice {
             );
 
             self.gl.enable_vertex_attrib_array(index);
-            debug!("Vertex attribute enabled with glGetError {}", self.gl.get_error());
+            trace!("Vertex attribute enabled with glGetError {}", self.gl.get_error());
         }
     }
 
@@ -177,6 +512,7 @@ impl GraphicsDevice {
 
             let buffer = RawIndexBuffer {
                 gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
                 id,
                 count,
             };
@@ -201,155 +537,1673 @@ impl GraphicsDevice {
         offset: usize,
     ) {
         unsafe {
-            info!("Set index buffer data");
-            self.bind_index_buffer(Some(&buffer));
+            trace!("Set index buffer data");
+            self.bind_index_buffer(Some(buffer));
 
             let u8_buffer = bytemuck::cast_slice(data);
-            println!("u8_buffer: {:?}", u8_buffer);
-
-            let byte_len = std::mem::size_of_val(data) / std::mem::size_of::<u8>();
-            let byte_slice = std::slice::from_raw_parts(data.as_ptr() as *const u8, byte_len);
-            println!("byte_slice: {:?}", byte_slice);
 
             self.gl.buffer_sub_data_u8_slice(
                 glow::ELEMENT_ARRAY_BUFFER,
                 (offset * mem::size_of::<f32>()) as i32,
                 u8_buffer
             );
-            debug!("Index data copied in buffer with glGetError {}", self.gl.get_error());
+            trace!("Index data copied in buffer with glGetError {}", self.gl.get_error());
+        }
+    }
+
+    /// Builds a program from GLSL, or with the `cross` feature from WGSL or
+    /// SPIR-V translated to the dialect of this context, see `ShaderSource`.
+    pub fn new_program<'a, V, F>(
+        &mut self,
+        vertex_code: V,
+        fragment_code: F
+    ) -> Result<RawProgram>
+    where
+        V: Into<ShaderSource<'a>>,
+        F: Into<ShaderSource<'a>>,
+    {
+        match (vertex_code.into(), fragment_code.into()) {
+            (ShaderSource::Glsl(vertex), ShaderSource::Glsl(fragment)) => {
+                self.new_program_with_attributes(vertex, fragment, &[])
+            }
+            #[cfg(feature = "cross")]
+            (vertex, fragment) => self.new_translated_program(&vertex, &fragment),
+        }
+    }
+
+    #[cfg(feature = "cross")]
+    fn new_translated_program(&mut self, vertex: &ShaderSource, fragment: &ShaderSource) -> Result<RawProgram> {
+        let dialect = self.get_shader_dialect();
+        info!("Translating shaders to {:?}", dialect);
+        let vertex = crate::cross::translate(vertex, ShaderStage::Vertex, dialect)?;
+        let fragment = crate::cross::translate(fragment, ShaderStage::Fragment, dialect)?;
+
+        let program = self.new_program_with_attributes(&vertex.source, &fragment.source, &[])?;
+        for compiled in &[vertex, fragment] {
+            for (name, binding) in &compiled.uniform_blocks {
+                unsafe {
+                    if let Some(index) = self.gl.get_uniform_block_index(program.id, name) {
+                        self.gl.uniform_block_binding(program.id, index, *binding);
+                    }
+                }
+            }
+            for (name, unit) in &compiled.samplers {
+                self.set_uniform(&program, name, Uniform::Int(*unit as i32));
+            }
+        }
+        Ok(program)
+    }
+
+    /// Binds attribute names to locations before linking, for GLSL versions
+    /// without `layout(location = n)`.
+    pub fn new_program_with_attributes(
+        &mut self,
+        vertex_code: &str,
+        fragment_code: &str,
+        attributes: &[(u32, &str)],
+    ) -> Result<RawProgram> {
+        self.build_program(vertex_code, fragment_code, attributes, false)
+    }
+
+    /// Same as `new_program_with_attributes`, loading the program from the
+    /// binary in `cache` when the driver accepts it and storing it otherwise.
+    /// Without program binary support it only compiles the program.
+    pub fn new_cached_program(
+        &mut self,
+        cache: &mut ProgramCache,
+        vertex_code: &str,
+        fragment_code: &str,
+        attributes: &[(u32, &str)],
+    ) -> Result<RawProgram> {
+        let functions = match self.extra_functions.program_binary {
+            Some(functions) => functions,
+            None => return self.new_program_with_attributes(vertex_code, fragment_code, attributes),
+        };
+
+        let attributes_key: Vec<String> = attributes.iter().map(|(index, name)| format!("{}={}", index, name)).collect();
+        let key = ProgramCache::get_key(&[
+            vertex_code,
+            fragment_code,
+            &attributes_key.join(","),
+            &self.get_renderer(),
+            &self.get_version(),
+        ]);
+
+        if let Some((format, data)) = cache.load(key) {
+            unsafe {
+                let program_id = self.gl.create_program()?;
+                functions.set_binary(program_id, format, &data);
+                if self.gl.get_program_link_status(program_id) {
+                    debug!("Loaded program binary {:016x}", key);
+                    cache.record_hit();
+                    return Ok(RawProgram {
+                        gl: Rc::clone(&self.gl),
+                        deleted: Rc::clone(&self.deleted),
+                        id: program_id,
+                    });
+                }
+                self.gl.delete_program(program_id);
+            }
+
+            warn!("Program binary {:016x} rejected by the driver, compiling it again", key);
+            cache.record_rejected();
+            cache.remove(key);
+        }
+
+        cache.record_miss();
+        let program = self.build_program(vertex_code, fragment_code, attributes, true)?;
+        if let Some((format, data)) = unsafe { functions.get_binary(program.id) } {
+            match cache.store(key, format, &data) {
+                Ok(()) => debug!("Stored program binary {:016x}", key),
+                Err(err) => warn!("Failed to store program binary {:016x}: {}", key, err),
+            }
         }
+        Ok(program)
     }
 
-    pub fn new_program(
+    fn build_program(
         &mut self,
         vertex_code: &str,
-        fragment_code: &str
+        fragment_code: &str,
+        attributes: &[(u32, &str)],
+        retrievable: bool,
+    ) -> Result<RawProgram> {
+        self.link_stages(
+            &[(ShaderStage::Vertex, vertex_code), (ShaderStage::Fragment, fragment_code)],
+            attributes,
+            retrievable,
+        )
+    }
+
+    /// Program from any combination of stages but compute, see `ProgramBuilder`.
+    /// Stages missing from this context, e.g. geometry before GL 3.2, fail.
+    pub fn new_program_with_stages(
+        &mut self,
+        stages: &[(ShaderStage, &str)],
+        attributes: &[(u32, &str)],
+    ) -> Result<RawProgram> {
+        let kinds: Vec<ShaderStage> = stages.iter().map(|(stage, _)| *stage).collect();
+        self.gl_version.check_stages(&kinds)?;
+        self.link_stages(stages, attributes, false)
+    }
+
+    /// Compute program, GL 4.3 or GLES 3.1 and later.
+    pub fn new_compute_program(&mut self, source: &str) -> Result<RawProgram> {
+        if !self.supports_stage(ShaderStage::Compute) {
+            return Err(failure::err_msg(format!("Compute shaders are not supported by {:?}", self.gl_version)).into());
+        }
+        self.link_stages(&[(ShaderStage::Compute, source)], &[], false)
+    }
+
+    pub fn supports_stage(&self, stage: ShaderStage) -> bool {
+        self.gl_version.supports_stage(stage)
+    }
+
+    /// Runs `program`, a compute program, on `x * y * z` work groups. Results
+    /// are visible to later commands once `memory_barrier` is called.
+    pub fn dispatch_compute(&mut self, program: &RawProgram, x: u32, y: u32, z: u32) {
+        if !self.supports_stage(ShaderStage::Compute) {
+            warn!("Compute shaders are not supported by {:?}", self.gl_version);
+            return;
+        }
+
+        self.bind_program(Some(program));
+        unsafe {
+            self.gl.dispatch_compute(x, y, z);
+        }
+    }
+
+    /// Orders writes of compute shaders, or image and storage buffer writes
+    /// of any stage, before the `barrier` accesses.
+    pub fn memory_barrier(&mut self, barrier: MemoryBarrier) {
+        match self.extra_functions.memory_barrier {
+            Some(memory_barrier) => unsafe { memory_barrier(barrier.0) },
+            None => warn!("glMemoryBarrier is not loaded, see load_extra_functions"),
+        }
+    }
+
+    fn link_stages(
+        &mut self,
+        stages: &[(ShaderStage, &str)],
+        attributes: &[(u32, &str)],
+        retrievable: bool,
     ) -> Result<RawProgram> {
-        // compile shaders from strings
         unsafe {
             info!("New shader program");
-            // vertex shader
-            let vertex_id = self.gl.create_shader(glow::VERTEX_SHADER)?;
-            self.gl.shader_source(vertex_id, &vertex_code);
-            self.gl.compile_shader(vertex_id);
-            if !self.gl.get_shader_compile_status(vertex_id) {
-                error!("Failed to compile vertex shader");
-                return Err(failure::err_msg(self.gl.get_shader_info_log(vertex_id)).into());
-            }
-            // fragment shader
-            let fragment_id = self.gl.create_shader(glow::FRAGMENT_SHADER)?;
-            self.gl.shader_source(fragment_id, &fragment_code);
-            self.gl.compile_shader(fragment_id);
-            if !self.gl.get_shader_compile_status(fragment_id) {
-                error!("Failed to compile fragment shader");
-                return Err(failure::err_msg(self.gl.get_shader_info_log(fragment_id)).into());
+            let mut shaders = Vec::with_capacity(stages.len());
+            for (stage, source) in stages {
+                match self.compile_shader(*stage, source) {
+                    Ok(shader_id) => shaders.push(shader_id),
+                    Err(err) => {
+                        for shader_id in shaders {
+                            self.gl.delete_shader(shader_id);
+                        }
+                        return Err(err);
+                    }
+                }
             }
-    
-            // shader program
+
             let program_id = self.gl.create_program()?;
-            self.gl.attach_shader(program_id, vertex_id);
-            self.gl.attach_shader(program_id, fragment_id);
+            for shader_id in &shaders {
+                self.gl.attach_shader(program_id, *shader_id);
+            }
+            for (index, name) in attributes {
+                self.gl.bind_attrib_location(program_id, *index, name);
+            }
+            if let (true, Some(functions)) = (retrievable, &self.extra_functions.program_binary) {
+                functions.set_retrievable_hint(program_id);
+            }
             self.gl.link_program(program_id);
-            if !self.gl.get_program_link_status(program_id) {
+            let linked = self.gl.get_program_link_status(program_id);
+
+            for shader_id in shaders {
+                self.gl.delete_shader(shader_id);
+            }
+            if !linked {
                 error!("Failed to link program");
-                return Err(failure::err_msg(self.gl.get_program_info_log(program_id)).into());
+                let log = self.gl.get_program_info_log(program_id);
+                self.gl.delete_program(program_id);
+                return Err(ShaderError::Link { log }.into());
             }
-    
-            self.gl.delete_shader(vertex_id);
-            self.gl.delete_shader(fragment_id);
-            
+
             debug!("Shader program created with glGetError {}", self.gl.get_error());
-            Ok(RawProgram { id:program_id })
+            Ok(RawProgram {
+                gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
+                id: program_id,
+            })
         }
     }
 
-    pub fn bind_vertex_buffer(&mut self, buffer: Option<&RawVertexBuffer>) {
-        unsafe {
-            let id = buffer.map(|x| x.id);
-    
-            if self.current_vertex_buffer != id {
-                self.gl.bind_buffer(glow::ARRAY_BUFFER, id);
-                self.current_vertex_buffer = id;
-            }
+    unsafe fn compile_shader(&self, stage: ShaderStage, source: &str) -> Result<ShaderId> {
+        let shader_id = self.gl.create_shader(stage.into())?;
+        self.gl.shader_source(shader_id, source);
+        self.gl.compile_shader(shader_id);
+        if !self.gl.get_shader_compile_status(shader_id) {
+            error!("Failed to compile {:?} shader", stage);
+            let log = self.gl.get_shader_info_log(shader_id);
+            self.gl.delete_shader(shader_id);
+            return Err(ShaderError::Compile { stage, log }.into());
         }
+        Ok(shader_id)
     }
 
-    pub fn bind_index_buffer(&mut self, buffer: Option<&RawIndexBuffer>) {
-        unsafe {
-            let id = buffer.map(|x| x.id);
-    
-            if self.current_index_buffer != id {
-                self.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, id);
-                self.current_index_buffer = id;
+    /// Runs files `vertex` and `fragment` through `preprocessor`, with the
+    /// header of this context unless it has one. Compile errors point to the
+    /// original files and lines.
+    pub fn new_preprocessed_program(
+        &mut self,
+        preprocessor: &ShaderPreprocessor,
+        vertex: &str,
+        fragment: &str,
+    ) -> Result<RawProgram> {
+        let header = self.get_glsl_header();
+        let header = preprocessor.get_header().unwrap_or(&header);
+        let vertex = preprocessor.process_with(vertex, Some(header), &[])?;
+        let fragment = preprocessor.process_with(fragment, Some(header), &[])?;
+
+        self.new_processed_program(&vertex, &fragment)
+    }
+
+    /// Compiles preprocessed sources, mapping the error logs back to the original lines.
+    pub fn new_processed_program(
+        &mut self,
+        vertex: &ProcessedShader,
+        fragment: &ProcessedShader,
+    ) -> Result<RawProgram> {
+        self.new_program(&vertex.source, &fragment.source).map_err(|err| {
+            match err.downcast::<ShaderError>() {
+                Ok(shader_error) => {
+                    let shader_error = match *shader_error {
+                        ShaderError::Compile { stage: ShaderStage::Vertex, log } => ShaderError::Compile {
+                            stage: ShaderStage::Vertex,
+                            log: vertex.line_map.map_log(&log),
+                        },
+                        ShaderError::Compile { stage: ShaderStage::Fragment, log } => ShaderError::Compile {
+                            stage: ShaderStage::Fragment,
+                            log: fragment.line_map.map_log(&log),
+                        },
+                        link => link,
+                    };
+                    shader_error.into()
+                }
+                Err(err) => err,
             }
-        }
+        })
     }
 
-    fn bind_program(&mut self, program: Option<&RawProgram>) {
+    /// Built-in program in the dialect of this context, see `get_shader_dialect`.
+    pub fn new_builtin_program(&mut self, shader: BuiltinShader) -> Result<RawProgram> {
+        let dialect = self.get_shader_dialect();
+        info!("New built-in program {:?} for {:?}", shader, dialect);
+
+        let (vertex, fragment) = shader.get_sources(dialect);
+        self.new_program_with_attributes(&vertex, &fragment, ATTRIBUTES)
+    }
+
+    /// GLSL dialect of the built-in programs, from the shading language version.
+    pub fn get_shader_dialect(&self) -> ShaderDialect {
+        ShaderDialect::from_shading_language_version(&self.get_shading_language_version())
+            .unwrap_or_else(|| ShaderDialect::from_gl_version(self.gl_version))
+    }
+
+    /// Sets uniform `name` of `program`, binding it. Unknown names, e.g.
+    /// optimized out by the driver, are ignored.
+    pub fn set_uniform(&mut self, program: &RawProgram, name: &str, value: Uniform) {
+        self.bind_program(Some(program));
+
         unsafe {
-            let id = program.map(|x| x.id);
+            let location = self.gl.get_uniform_location(program.id, name);
+            if location.is_none() {
+                debug!("No uniform {} in program", name);
+                return;
+            }
 
-            if self.current_program != id {
-                self.gl.use_program(id);
-                self.current_program = id;
+            match value {
+                Uniform::Int(x) => self.gl.uniform_1_i32(location, x),
+                Uniform::Float(x) => self.gl.uniform_1_f32(location, x),
+                Uniform::Vec2(v) => self.gl.uniform_2_f32_slice(location, &v),
+                Uniform::Vec3(v) => self.gl.uniform_3_f32_slice(location, &v),
+                Uniform::Vec4(v) => self.gl.uniform_4_f32_slice(location, &v),
+                Uniform::Mat4(m) => self.gl.uniform_matrix_4_f32_slice(location, false, &m),
             }
         }
     }
 
-}
+    /// Uniform buffer holding one `T`, whose layout is checked first. Uniform
+    /// blocks are std140 only.
+    pub fn new_uniform_buffer<T: Block>(&mut self, value: &T, usage: BufferUsage) -> Result<UniformBuffer<T>> {
+        if T::LAYOUT != BlockLayout::Std140 {
+            return Err(failure::err_msg(format!(
+                "Uniform blocks are std140, {} is {:?}",
+                std::any::type_name::<T>(),
+                T::LAYOUT
+            ))
+            .into());
+        }
+        check_layout::<T>()?;
+        let raw = self.new_block_buffer(BlockKind::Uniform, bytemuck::bytes_of(value), usage)?;
+        Ok(UniformBuffer { raw, marker: PhantomData })
+    }
 
-macro_rules! handle_impls {
-    ($name:ty, $delete:ident) => {
-        impl PartialEq for $name {
-            fn eq(&self, other: &$name) -> bool {
-                self.id == other.id
-            }
+    pub fn set_uniform_buffer_data<T: Block>(&mut self, buffer: &UniformBuffer<T>, value: &T) {
+        self.set_block_buffer_data(&buffer.raw, 0, bytemuck::bytes_of(value));
+    }
+
+    /// Storage buffer holding an array of `T`, whose layout is checked first.
+    /// `data` can't be empty, an empty range can't be bound.
+    pub fn new_storage_buffer<T: Block>(&mut self, data: &[T], usage: BufferUsage) -> Result<StorageBuffer<T>> {
+        check_layout::<T>()?;
+        let raw = self.new_block_buffer(BlockKind::Storage, bytemuck::cast_slice(data), usage)?;
+        Ok(StorageBuffer { raw, count: data.len(), marker: PhantomData })
+    }
+
+    /// Replaces elements from `offset` on, ignoring data past the end of the buffer.
+    pub fn set_storage_buffer_data<T: Block>(&mut self, buffer: &StorageBuffer<T>, data: &[T], offset: usize) {
+        if offset + data.len() > buffer.count {
+            warn!("Storage buffer data out of range: {} + {} > {}", offset, data.len(), buffer.count);
+            return;
         }
+        self.set_block_buffer_data(&buffer.raw, offset * mem::size_of::<T>(), bytemuck::cast_slice(data));
+    }
 
-        impl Drop for $name {
-            fn drop(&mut self) {
-                unsafe {
-                    self.gl.$delete(self.id);
+    pub fn bind_uniform_buffer<T>(&mut self, buffer: &UniformBuffer<T>, binding: u32) {
+        self.bind_block_buffer(&buffer.raw, binding);
+    }
+
+    pub fn bind_storage_buffer<T>(&mut self, buffer: &StorageBuffer<T>, binding: u32) {
+        self.bind_block_buffer(&buffer.raw, binding);
+    }
+
+    /// Binds the whole of `buffer` to the indexed binding point `binding` of
+    /// its kind, the one programs get from `apply_block_bindings`.
+    pub fn bind_block_buffer(&mut self, buffer: &RawBlockBuffer, binding: u32) {
+        unsafe {
+            self.gl.bind_buffer_range(buffer.kind.into(), binding, Some(buffer.id), 0, buffer.size as i32);
+        }
+    }
+
+    /// Points the blocks of `program` named in `bindings` to their binding
+    /// points, returning how many the program has.
+    pub fn apply_block_bindings(&mut self, program: &RawProgram, bindings: &BlockBindings) -> usize {
+        let mut applied = 0;
+        for (name, kind, binding) in bindings.get_bindings() {
+            if !self.gl_version.supports_block(*kind) {
+                continue;
+            }
+
+            unsafe {
+                let index = match kind {
+                    BlockKind::Uniform => self.gl.get_uniform_block_index(program.id, name),
+                    BlockKind::Storage => self.gl.get_shader_storage_block_index(program.id, name),
+                };
+                match (kind, index) {
+                    (BlockKind::Uniform, Some(index)) => self.gl.uniform_block_binding(program.id, index, *binding),
+                    (BlockKind::Storage, Some(index)) => self.gl.shader_storage_block_binding(program.id, index, *binding),
+                    (_, None) => {
+                        debug!("No block {} in program", name);
+                        continue;
+                    }
                 }
             }
+            applied += 1;
         }
-    };
-}
+        applied
+    }
 
-#[derive(Clone, Copy)]
-pub enum BufferUsage {
-    StaticDraw,
-    DynamicDraw,
-}
+    fn new_block_buffer(&mut self, kind: BlockKind, data: &[u8], usage: BufferUsage) -> Result<RawBlockBuffer> {
+        if !self.gl_version.supports_block(kind) {
+            return Err(failure::err_msg(format!("{:?} blocks are not supported by {:?}", kind, self.gl_version)).into());
+        }
+        if data.is_empty() {
+            return Err(failure::err_msg(format!("Empty {:?} block buffer", kind)).into());
+        }
 
-impl From<BufferUsage> for u32 {
-    fn from(buffer_usage: BufferUsage) -> u32{
-        match buffer_usage {
-            BufferUsage::StaticDraw => glow::STATIC_DRAW,
-            BufferUsage::DynamicDraw => glow::DYNAMIC_DRAW,
+        unsafe {
+            info!("New {:?} block buffer of {} bytes", kind, data.len());
+            let id = self.gl.create_buffer()?;
+            self.gl.bind_buffer(kind.into(), Some(id));
+            self.gl.buffer_data_u8_slice(kind.into(), data, usage.into());
+
+            debug!("Block buffer created with glGetError {}", self.gl.get_error());
+            Ok(RawBlockBuffer {
+                gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
+                id,
+                kind,
+                size: data.len(),
+            })
         }
     }
-}
 
-#[derive(Debug)]
-pub struct RawVertexBuffer {
-    gl: Rc<GlowContext>,
-    id: BufferId,
-    count: usize,
-    stride: usize,
-}
+    fn set_block_buffer_data(&mut self, buffer: &RawBlockBuffer, offset: usize, data: &[u8]) {
+        unsafe {
+            self.gl.bind_buffer(buffer.kind.into(), Some(buffer.id));
+            self.gl.buffer_sub_data_u8_slice(buffer.kind.into(), offset as i32, data);
+        }
+    }
 
-handle_impls!(RawVertexBuffer, delete_buffer);
+    /// `glBufferStorage`, GL 4.4, `GL_ARB_buffer_storage` or `GL_EXT_buffer_storage`
+    /// on GLES 3.1.
+    pub fn supports_buffer_storage(&self) -> bool {
+        self.buffer_storage
+    }
 
-#[derive(Debug)]
-pub struct RawIndexBuffer {
-    gl: Rc<GlowContext>,
-    id: BufferId,
-    count: usize,
-}
+    fn query_buffer_storage(&self) -> bool {
+        let version = (self.gl_version.major, self.gl_version.minor);
+        match self.gl_version.api {
+            GlApi::OpenGl => version >= (4, 4) || self.has_extension("GL_ARB_buffer_storage"),
+            GlApi::OpenGlEs => version >= (3, 1) && self.has_extension("GL_EXT_buffer_storage"),
+        }
+    }
 
-handle_impls!(RawIndexBuffer, delete_buffer);
+    /// Buffer for data rewritten every frame, e.g. batched sprites and UI,
+    /// holding up to `frame_size` bytes per frame. Persistently mapped when
+    /// `supports_buffer_storage`, orphaned at each frame otherwise.
+    pub fn new_stream_buffer(&mut self, kind: StreamKind, frame_size: usize) -> Result<StreamBuffer> {
+        if self.supports_buffer_storage() {
+            let allocator = StreamAllocator::new(frame_size, STREAM_FRAMES);
+            let mut stream = self.create_stream_buffer(kind, allocator)?;
+            let size = stream.allocator.get_total_size() as i32;
+            let flags = glow::MAP_WRITE_BIT | glow::MAP_PERSISTENT_BIT | glow::MAP_COHERENT_BIT;
 
+            let mapping = unsafe {
+                self.gl.buffer_storage(kind.into(), size, None, flags);
+                self.gl.map_buffer_range(kind.into(), 0, size, flags)
+            };
+            if !mapping.is_null() {
+                info!("New persistent {:?} stream buffer of {}x{} bytes", kind, STREAM_FRAMES, frame_size);
+                stream.mapping = Some(mapping);
+                return Ok(stream);
+            }
+            warn!("Failed to map stream buffer with glGetError {}, orphaning it instead", unsafe { self.gl.get_error() });
+        }
+
+        let stream = self.create_stream_buffer(kind, StreamAllocator::new(frame_size, 1))?;
+        unsafe {
+            self.gl.buffer_data_size(kind.into(), frame_size as i32, glow::STREAM_DRAW);
+        }
+        info!("New orphaning {:?} stream buffer of {} bytes", kind, frame_size);
+        Ok(stream)
+    }
+
+    /// Starts writing a new frame. Waits when the GPU still reads the frame
+    /// written `STREAM_FRAMES` frames ago, which only happens when it is
+    /// that far behind.
+    pub fn begin_stream_frame(&mut self, stream: &mut StreamBuffer) {
+        let frame = stream.allocator.begin_frame();
+
+        if stream.mapping.is_none() {
+            self.bind_stream_buffer(stream);
+            unsafe {
+                self.gl.buffer_data_size(stream.kind.into(), stream.allocator.get_frame_size() as i32, glow::STREAM_DRAW);
+            }
+            return;
+        }
+
+        if let Some(fence) = stream.fences[frame].take() {
+            unsafe {
+                loop {
+                    match self.gl.client_wait_sync(fence, glow::SYNC_FLUSH_COMMANDS_BIT, 1_000_000) {
+                        glow::ALREADY_SIGNALED | glow::CONDITION_SATISFIED => break,
+                        glow::WAIT_FAILED => {
+                            warn!("Waiting on stream buffer fence failed");
+                            break;
+                        }
+                        _ => debug!("Waiting on stream buffer frame {}", frame),
+                    }
+                }
+                self.gl.delete_sync(fence);
+            }
+        }
+    }
+
+    /// Copies `data` in the current frame, None when the frame is full.
+    pub fn push_stream_data<T: bytemuck::Pod>(&mut self, stream: &mut StreamBuffer, data: &[T]) -> Option<StreamSlice> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let slice = match stream.allocator.allocate(bytes.len()) {
+            Some(slice) => slice,
+            None => {
+                warn!("Stream buffer frame full, {} + {} bytes", stream.allocator.get_used(), bytes.len());
+                return None;
+            }
+        };
+
+        match stream.mapping {
+            Some(mapping) => unsafe {
+                ptr::copy_nonoverlapping(bytes.as_ptr(), mapping.add(slice.offset), bytes.len());
+            },
+            None => {
+                self.bind_stream_buffer(stream);
+                unsafe {
+                    self.gl.buffer_sub_data_u8_slice(stream.kind.into(), slice.offset as i32, bytes);
+                }
+            }
+        }
+        Some(slice)
+    }
+
+    /// Ends the frame, after the draws reading it so its fence covers them.
+    pub fn end_stream_frame(&mut self, stream: &mut StreamBuffer) {
+        if stream.mapping.is_none() {
+            return;
+        }
+
+        let frame = stream.allocator.get_frame();
+        unsafe {
+            match self.gl.fence_sync(glow::SYNC_GPU_COMMANDS_COMPLETE, 0) {
+                Ok(fence) => stream.fences[frame] = Some(fence),
+                Err(err) => warn!("Failed to create stream buffer fence: {}", err),
+            }
+        }
+    }
+
+    /// Binds `stream` as the vertex or index buffer, through the binding cache.
+    pub fn bind_stream_buffer(&mut self, stream: &StreamBuffer) {
+        self.forget_deleted();
+        unsafe {
+            let (current, target) = match stream.kind {
+                StreamKind::Vertex => (&mut self.cache.current_vertex_buffer, glow::ARRAY_BUFFER),
+                StreamKind::Index => (&mut self.cache.current_index_buffer, glow::ELEMENT_ARRAY_BUFFER),
+            };
+            if *current != Some(stream.id) {
+                self.gl.bind_buffer(target, Some(stream.id));
+                *current = Some(stream.id);
+            }
+        }
+    }
+
+    /// Same as `set_vertex_buffer_attribute` for vertices pushed as `slice`,
+    /// `stride` and `offset` counting floats.
+    pub fn set_stream_vertex_attribute(
+        &mut self,
+        stream: &StreamBuffer,
+        slice: StreamSlice,
+        index: u32,
+        size: i32,
+        stride: usize,
+        offset: usize,
+    ) {
+        self.bind_stream_buffer(stream);
+        unsafe {
+            self.gl.vertex_attrib_pointer_f32(
+                index,
+                size,
+                glow::FLOAT,
+                false,
+                (stride * mem::size_of::<f32>()) as i32,
+                (slice.offset + offset * mem::size_of::<f32>()) as i32,
+            );
+            self.gl.enable_vertex_attrib_array(index);
+        }
+    }
+
+    /// Draws the `u32` indices pushed as `slice`, relative to the vertex slice
+    /// given to `set_stream_vertex_attribute`.
+    pub fn draw_stream(
+        &mut self,
+        indices: &StreamBuffer,
+        slice: StreamSlice,
+        program: &RawProgram,
+        primitive: Primitive,
+    ) {
+        if !self.set_patch_vertices(primitive) {
+            return;
+        }
+
+        self.bind_stream_buffer(indices);
+        self.bind_program(Some(program));
+
+        if self.state_validation {
+            self.validate_state_cache();
+        }
+
+        unsafe {
+            self.gl.draw_elements(
+                primitive.into(),
+                (slice.size / mem::size_of::<u32>()) as i32,
+                glow::UNSIGNED_INT,
+                slice.offset as i32,
+            );
+        }
+    }
+
+    fn create_stream_buffer(&mut self, kind: StreamKind, allocator: StreamAllocator) -> Result<StreamBuffer> {
+        // an index buffer binding goes to the bound vertex array
+        if kind == StreamKind::Index {
+            self.bind_vertex_array(None);
+        }
+
+        let id = unsafe { self.gl.create_buffer()? };
+        let stream = StreamBuffer {
+            gl: Rc::clone(&self.gl),
+            deleted: Rc::clone(&self.deleted),
+            id,
+            kind,
+            allocator,
+            mapping: None,
+            fences: vec![None; STREAM_FRAMES],
+        };
+        self.bind_stream_buffer(&stream);
+        Ok(stream)
+    }
+
+    pub fn new_texture(
+        &mut self,
+        width: i32,
+        height: i32,
+        filter: FilterMode,
+    ) -> Result<RawTexture> {
+        unsafe {
+            info!("New texture {}x{}", width, height);
+            let id = self.gl.create_texture()?;
+
+            let texture = RawTexture {
+                gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
+                id,
+                width,
+                height,
+            };
+
+            self.bind_texture(0, Some(&texture));
+            self.gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA8 as i32,
+                width,
+                height,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+            self.gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, filter.into());
+            self.gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, filter.into());
+            self.gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            self.gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+
+            debug!("Texture created with glGetError {}", self.gl.get_error());
+            Ok(texture)
+        }
+    }
+
+    /// Replaces the whole content of `texture` with RGBA8 `data`.
+    pub fn set_texture_data(&mut self, texture: &RawTexture, data: &[u8]) {
+        unsafe {
+            trace!("Set texture data");
+            self.bind_texture(0, Some(texture));
+
+            self.gl.tex_sub_image_2d_u8_slice(
+                glow::TEXTURE_2D,
+                0,
+                0,
+                0,
+                texture.width,
+                texture.height,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                Some(data),
+            );
+            trace!("Texture data copied with glGetError {}", self.gl.get_error());
+        }
+    }
+
+    /// Binds `texture` on texture `unit`, the value of the sampler uniforms
+    /// reading it. Leaves `unit` active.
+    pub fn bind_texture(&mut self, unit: u32, texture: Option<&RawTexture>) {
+        self.forget_deleted();
+        unsafe {
+            let id = texture.map(|x| x.id);
+
+            if self.cache.active_texture_unit != unit {
+                self.gl.active_texture(glow::TEXTURE0 + unit);
+                self.cache.active_texture_unit = unit;
+            }
+            if self.cache.textures.get(&unit) != Some(&id) {
+                self.gl.bind_texture(glow::TEXTURE_2D, id);
+                self.cache.textures.insert(unit, id);
+            }
+        }
+    }
+
+    /// A vertex array keeps its own attribute layout and index buffer, so
+    /// independent renderers don't overwrite each other's attributes.
+    pub fn new_vertex_array(&mut self) -> Result<RawVertexArray> {
+        if !self.gl_version.supports_vertex_arrays() {
+            return Err(failure::err_msg(format!("Vertex arrays are not supported by {:?}", self.gl_version)).into());
+        }
+
+        unsafe {
+            info!("New vertex array");
+            let id = self.gl.create_vertex_array()?;
+
+            Ok(RawVertexArray {
+                gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
+                id,
+            })
+        }
+    }
+
+    /// Binds `vertex_array`, or the default vertex array of the context when
+    /// `None`. Does nothing without vertex arrays.
+    pub fn bind_vertex_array(&mut self, vertex_array: Option<&RawVertexArray>) {
+        self.forget_deleted();
+        let id = vertex_array.map(|x| x.id).or(self.cache.default_vertex_array);
+        if id.is_none() {
+            return;
+        }
+
+        if self.cache.current_vertex_array != id {
+            unsafe { self.gl.bind_vertex_array(id) };
+
+            // the index buffer binding belongs to the vertex array
+            let cache = &mut self.cache;
+            if let (Some(previous), Some(index_buffer)) = (cache.current_vertex_array, cache.current_index_buffer) {
+                cache.index_buffers.insert(previous, index_buffer);
+            }
+            cache.current_index_buffer = id.and_then(|id| cache.index_buffers.remove(&id));
+            cache.current_vertex_array = id;
+        }
+    }
+
+    pub fn set_alpha_blending(&mut self, enabled: bool) {
+        if self.cache.alpha_blending != enabled {
+            unsafe {
+                if enabled {
+                    self.gl.enable(glow::BLEND);
+                    self.gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+                } else {
+                    self.gl.disable(glow::BLEND);
+                }
+            }
+            self.cache.alpha_blending = enabled;
+        }
+    }
+
+    /// Draws triangle edges only. Not available on GLES, where it does nothing.
+    pub fn set_wireframe(&mut self, enabled: bool) {
+        if self.cache.wireframe == enabled {
+            return;
+        }
+        if self.gl_version.api != GlApi::OpenGl {
+            warn!("Wireframe is not supported on {:?}", self.gl_version.api);
+            return;
+        }
+
+        let mode = if enabled { glow::LINE } else { glow::FILL };
+        unsafe { self.gl.polygon_mode(glow::FRONT_AND_BACK, mode) };
+        self.cache.wireframe = enabled;
+    }
+
+    pub fn get_wireframe(&self) -> bool {
+        self.cache.wireframe
+    }
+
+    /// Sets the vertex count of patches before drawing them, false when
+    /// tessellation isn't supported.
+    fn set_patch_vertices(&mut self, primitive: Primitive) -> bool {
+        let vertices = match primitive {
+            Primitive::Patches(vertices) => vertices,
+            _ => return true,
+        };
+        if !self.supports_stage(ShaderStage::TessControl) {
+            warn!("Patches are not supported by {:?}", self.gl_version);
+            return false;
+        }
+
+        if self.cache.patch_vertices != Some(vertices) {
+            unsafe { self.gl.patch_parameter_i32(glow::PATCH_VERTICES, vertices as i32) };
+            self.cache.patch_vertices = Some(vertices);
+        }
+        true
+    }
+
+    pub fn get_alpha_blending(&self) -> bool {
+        self.cache.alpha_blending
+    }
+
+    /// Renderer, version and vendor, for bug reports.
+    pub fn get_info(&self) -> String {
+        format!(
+            "Renderer: {}\nVersion: {}\nVendor: {}\nShading language: {}\n",
+            self.get_renderer(),
+            self.get_version(),
+            self.get_vendor(),
+            self.get_shading_language_version(),
+        )
+    }
+
+    pub fn new_render_target(
+        &mut self,
+        width: i32,
+        height: i32,
+        filter: FilterMode,
+    ) -> Result<RawRenderTarget> {
+        unsafe {
+            info!("New render target {}x{}", width, height);
+            let texture = self.new_texture(width, height, filter)?;
+            let id = self.gl.create_framebuffer()?;
+
+            let target = RawRenderTarget {
+                gl: Rc::clone(&self.gl),
+                deleted: Rc::clone(&self.deleted),
+                id,
+                texture,
+            };
+
+            self.bind_render_target(Some(&target));
+            self.gl.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::TEXTURE_2D,
+                Some(target.texture.id),
+                0,
+            );
+
+            let status = self.gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            self.bind_render_target(None);
+
+            if status != glow::FRAMEBUFFER_COMPLETE {
+                error!("Render target is incomplete");
+                return Err(failure::err_msg(format!("Incomplete framebuffer, status {:#x}", status)).into());
+            }
+
+            debug!("Render target created with glGetError {}", self.gl.get_error());
+            Ok(target)
+        }
+    }
+
+    /// Copies `source` to the rectangle `(x, y, width, height)` of the default framebuffer.
+    pub fn blit_render_target(
+        &mut self,
+        source: &RawRenderTarget,
+        destination: (i32, i32, i32, i32),
+        filter: FilterMode,
+    ) -> Result<()> {
+        if !self.gl_version.supports_framebuffer_blit() {
+            return Err(failure::err_msg(format!("Blitting render targets is not supported by {:?}", self.gl_version)).into());
+        }
+
+        unsafe {
+            let (x, y, width, height) = destination;
+
+            self.gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(source.id));
+            self.gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, None);
+            self.gl.blit_framebuffer(
+                0,
+                0,
+                source.texture.width,
+                source.texture.height,
+                x,
+                y,
+                x + width,
+                y + height,
+                glow::COLOR_BUFFER_BIT,
+                filter.into(),
+            );
+            self.gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            self.cache.current_framebuffer = None;
+        }
+        Ok(())
+    }
+
+    pub fn bind_render_target(&mut self, target: Option<&RawRenderTarget>) {
+        self.forget_deleted();
+        unsafe {
+            let id = target.map(|x| x.id);
+
+            if self.cache.current_framebuffer != id {
+                self.gl.bind_framebuffer(glow::FRAMEBUFFER, id);
+                self.cache.current_framebuffer = id;
+            }
+        }
+    }
+
+    /// Same as `new_program` for bodies written like the built-in programs,
+    /// with the `ATTRIBUTE`, `VARYING` and `FragColor` keywords, prefixed with
+    /// the prelude of `get_shader_dialect`. Attributes are bound to `ATTRIBUTES`.
+    pub fn new_versioned_program(
+        &mut self,
+        vertex_body: &str,
+        fragment_body: &str
+    ) -> Result<RawProgram> {
+        let dialect = self.get_shader_dialect();
+        self.new_program_with_attributes(
+            &format!("{}{}", dialect.vertex_prelude(), vertex_body),
+            &format!("{}{}", dialect.fragment_prelude(), fragment_body),
+            ATTRIBUTES,
+        )
+    }
+
+    /// Registers the GL context just made current, sharing objects with the
+    /// others, and switches to it. Vertex attributes are vertex array state
+    /// and have to be set again for each context.
+    pub fn add_context(&mut self) -> Result<usize> {
+        let cache = unsafe { BindingCache::new(&self.gl, self.gl_version)? };
+        let id = self.next_context_id;
+        self.next_context_id += 1;
+
+        let previous = mem::replace(&mut self.cache, cache);
+        self.other_caches.insert(self.context_id, previous);
+        self.context_id = id;
+
+        info!("Added GL context {}", id);
+        Ok(id)
+    }
+
+    /// Switches to the binding cache of `id`, whose GL context the caller made current.
+    pub fn set_current_context(&mut self, id: usize) -> Result<()> {
+        if id == self.context_id {
+            return Ok(());
+        }
+
+        let cache = self
+            .other_caches
+            .remove(&id)
+            .ok_or_else(|| failure::err_msg(format!("Unknown GL context {}", id)))?;
+        let previous = mem::replace(&mut self.cache, cache);
+        self.other_caches.insert(self.context_id, previous);
+        self.context_id = id;
+        Ok(())
+    }
+
+    /// Forgets context `id`, current on this thread and about to be destroyed
+    /// with its window, deleting its vertex array. Switches to the binding
+    /// cache of `next`, whose GL context the caller makes current right after.
+    pub fn remove_context(&mut self, id: usize, next: usize) -> Result<()> {
+        if id != self.context_id {
+            return Err(failure::err_msg(format!("GL context {} is not the current one", id)).into());
+        }
+
+        let cache = self
+            .other_caches
+            .remove(&next)
+            .ok_or_else(|| failure::err_msg(format!("Unknown GL context {}", next)))?;
+        let removed = mem::replace(&mut self.cache, cache);
+        self.context_id = next;
+
+        if let Some(vertex_array) = removed.default_vertex_array {
+            unsafe { self.gl.delete_vertex_array(vertex_array) };
+        }
+        info!("Removed GL context {}", id);
+        Ok(())
+    }
+
+    pub fn get_current_context(&self) -> usize {
+        self.context_id
+    }
+
+    /// Resynchronizes the binding cache after foreign code touched GL, restoring
+    /// the ckrl vertex array and viewport.
+    pub fn reset_state_cache(&mut self) {
+        unsafe {
+            if let Some(vertex_array) = self.cache.current_vertex_array {
+                self.gl.bind_vertex_array(Some(vertex_array));
+            }
+            let (x, y, width, height) = self.cache.viewport;
+            self.gl.viewport(x, y, width, height);
+            if self.gl_version.api == GlApi::OpenGl {
+                let mode = if self.cache.wireframe { glow::LINE } else { glow::FILL };
+                self.gl.polygon_mode(glow::FRONT_AND_BACK, mode);
+            }
+        }
+
+        self.cache.patch_vertices = None;
+        self.cache.current_vertex_buffer = self.query_binding(glow::ARRAY_BUFFER_BINDING);
+        self.cache.current_index_buffer = self.query_binding(glow::ELEMENT_ARRAY_BUFFER_BINDING);
+        self.cache.current_program = self.query_binding(glow::CURRENT_PROGRAM);
+        self.cache.current_framebuffer = self.query_binding(glow::FRAMEBUFFER_BINDING);
+        self.cache.active_texture_unit = unsafe { self.gl.get_parameter_i32(glow::ACTIVE_TEXTURE) } as u32 - glow::TEXTURE0;
+        self.cache.textures.clear();
+        let texture = self.query_binding(glow::TEXTURE_BINDING_2D);
+        self.cache.textures.insert(self.cache.active_texture_unit, texture);
+        self.cache.alpha_blending = unsafe { self.gl.get_parameter_i32(glow::BLEND) } != 0;
+        debug!("State cache reset with glGetError {}", unsafe { self.gl.get_error() });
+    }
+
+    /// Runs raw GL calls, then resynchronizes the binding cache they may have changed.
+    pub fn with_raw_gl<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&GlowContext) -> R,
+    {
+        let result = f(&self.gl);
+        self.reset_state_cache();
+        result
+    }
+
+    /// Runs `validate_state_cache` before each draw. On in debug builds only,
+    /// the glGet calls stall the pipeline.
+    pub fn set_state_validation(&mut self, enabled: bool) {
+        self.state_validation = enabled;
+    }
+
+    pub fn get_state_validation(&self) -> bool {
+        self.state_validation
+    }
+
+    /// Compares the binding cache against the GL bindings, logging every drift.
+    /// Runs before each draw while `get_state_validation` is on.
+    pub fn validate_state_cache(&self) -> bool {
+        let texture = self.cache.textures.get(&self.cache.active_texture_unit).copied().flatten();
+        let checks = [
+            ("vertex array", self.cache.current_vertex_array, glow::VERTEX_ARRAY_BINDING),
+            ("vertex buffer", self.cache.current_vertex_buffer, glow::ARRAY_BUFFER_BINDING),
+            ("index buffer", self.cache.current_index_buffer, glow::ELEMENT_ARRAY_BUFFER_BINDING),
+            ("program", self.cache.current_program, glow::CURRENT_PROGRAM),
+            ("framebuffer", self.cache.current_framebuffer, glow::FRAMEBUFFER_BINDING),
+            ("texture", texture, glow::TEXTURE_BINDING_2D),
+        ];
+
+        let mut valid = true;
+        for (name, cached, parameter) in checks.iter() {
+            if *parameter == glow::VERTEX_ARRAY_BINDING && !self.gl_version.supports_vertex_arrays() {
+                continue;
+            }
+
+            let bound = self.query_binding(*parameter);
+            if bound != *cached {
+                error!("State cache drift on {}: cached {:?}, bound {:?}", name, cached, bound);
+                valid = false;
+            }
+        }
+        valid
+    }
+
+    fn query_binding(&self, parameter: u32) -> Option<u32> {
+        match unsafe { self.gl.get_parameter_i32(parameter) } {
+            0 => None,
+            id => Some(id as u32),
+        }
+    }
+
+    /// Updates the binding caches of every context with the handles dropped
+    /// since the last call, done before each lookup.
+    fn forget_deleted(&mut self) {
+        if self.deleted.borrow().is_empty() {
+            return;
+        }
+
+        let deleted = mem::take(&mut *self.deleted.borrow_mut());
+        for handle in deleted {
+            self.cache.forget(handle);
+            for cache in self.other_caches.values_mut() {
+                cache.forget(handle);
+            }
+        }
+    }
+
+    pub fn bind_vertex_buffer(&mut self, buffer: Option<&RawVertexBuffer>) {
+        self.forget_deleted();
+        unsafe {
+            let id = buffer.map(|x| x.id);
+    
+            if self.cache.current_vertex_buffer != id {
+                self.gl.bind_buffer(glow::ARRAY_BUFFER, id);
+                self.cache.current_vertex_buffer = id;
+            }
+        }
+    }
+
+    pub fn bind_index_buffer(&mut self, buffer: Option<&RawIndexBuffer>) {
+        self.forget_deleted();
+        unsafe {
+            let id = buffer.map(|x| x.id);
+    
+            if self.cache.current_index_buffer != id {
+                self.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, id);
+                self.cache.current_index_buffer = id;
+            }
+        }
+    }
+
+    fn bind_program(&mut self, program: Option<&RawProgram>) {
+        self.forget_deleted();
+        unsafe {
+            let id = program.map(|x| x.id);
+
+            if self.cache.current_program != id {
+                self.gl.use_program(id);
+                self.cache.current_program = id;
+            }
+        }
+    }
+
+}
+
+impl Drop for GraphicsDevice {
+    fn drop(&mut self) {
+        // their vertex arrays can only be deleted while their context is current
+        if !self.other_caches.is_empty() {
+            warn!("Dropping the device with {} other GL contexts, see remove_context", self.other_caches.len());
+        }
+        if let Some(vertex_array) = self.cache.default_vertex_array {
+            unsafe { self.gl.delete_vertex_array(vertex_array) };
+        }
+    }
+}
+
+/// `$before`, a method of the handle, runs before the GL object is deleted.
+macro_rules! handle_impls {
+    ($name:ty, $delete:ident, $handle:ident $(, $before:ident)?) => {
+        impl PartialEq for $name {
+            fn eq(&self, other: &$name) -> bool {
+                self.id == other.id
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe {
+                    $(self.$before();)?
+                    self.gl.$delete(self.id);
+                }
+                self.deleted.borrow_mut().push(DeletedHandle::$handle(self.id));
+            }
+        }
+    };
+}
+
+/// Source of a shader stage for `GraphicsDevice::new_program`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShaderSource<'a> {
+    Glsl(&'a str),
+    /// Translated by naga, `entry_point` being the `@vertex` or `@fragment` function.
+    #[cfg(feature = "cross")]
+    Wgsl { source: &'a str, entry_point: &'a str },
+    /// Little-endian SPIR-V words, translated by naga.
+    #[cfg(feature = "cross")]
+    SpirV { data: &'a [u8], entry_point: &'a str },
+}
+
+impl<'a> From<&'a str> for ShaderSource<'a> {
+    fn from(source: &'a str) -> Self {
+        ShaderSource::Glsl(source)
+    }
+}
+
+impl<'a> From<&'a String> for ShaderSource<'a> {
+    fn from(source: &'a String) -> Self {
+        ShaderSource::Glsl(source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    TessControl,
+    TessEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+
+impl From<ShaderStage> for u32 {
+    fn from(stage: ShaderStage) -> u32 {
+        match stage {
+            ShaderStage::Vertex => glow::VERTEX_SHADER,
+            ShaderStage::TessControl => glow::TESS_CONTROL_SHADER,
+            ShaderStage::TessEvaluation => glow::TESS_EVALUATION_SHADER,
+            ShaderStage::Geometry => glow::GEOMETRY_SHADER,
+            ShaderStage::Fragment => glow::FRAGMENT_SHADER,
+            ShaderStage::Compute => glow::COMPUTE_SHADER,
+        }
+    }
+}
+
+/// Stages of a program made with `new_program_with_stages`, sources owned.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramBuilder {
+    stages: Vec<(ShaderStage, String)>,
+    attributes: Vec<(u32, String)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the source of `stage`, replacing the previous one.
+    pub fn with_stage(&mut self, stage: ShaderStage, source: &str) -> &mut Self {
+        self.stages.retain(|(existing, _)| *existing != stage);
+        self.stages.push((stage, source.to_string()));
+        self
+    }
+
+    pub fn with_vertex(&mut self, source: &str) -> &mut Self {
+        self.with_stage(ShaderStage::Vertex, source)
+    }
+
+    pub fn with_tess_control(&mut self, source: &str) -> &mut Self {
+        self.with_stage(ShaderStage::TessControl, source)
+    }
+
+    pub fn with_tess_evaluation(&mut self, source: &str) -> &mut Self {
+        self.with_stage(ShaderStage::TessEvaluation, source)
+    }
+
+    pub fn with_geometry(&mut self, source: &str) -> &mut Self {
+        self.with_stage(ShaderStage::Geometry, source)
+    }
+
+    pub fn with_fragment(&mut self, source: &str) -> &mut Self {
+        self.with_stage(ShaderStage::Fragment, source)
+    }
+
+    /// Binds attribute `name` to `index` before linking.
+    pub fn with_attribute(&mut self, index: u32, name: &str) -> &mut Self {
+        self.attributes.push((index, name.to_string()));
+        self
+    }
+
+    pub fn get_stages(&self) -> Vec<ShaderStage> {
+        self.stages.iter().map(|(stage, _)| *stage).collect()
+    }
+
+    pub fn build(&self, device: &mut GraphicsDevice) -> Result<RawProgram> {
+        let stages: Vec<(ShaderStage, &str)> = self.stages.iter().map(|(stage, source)| (*stage, source.as_str())).collect();
+        let attributes: Vec<(u32, &str)> = self.attributes.iter().map(|(index, name)| (*index, name.as_str())).collect();
+        device.new_program_with_stages(&stages, &attributes)
+    }
+}
+
+/// Accesses ordered by `GraphicsDevice::memory_barrier`, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBarrier(u32);
+
+impl MemoryBarrier {
+    pub const VERTEX_ATTRIB_ARRAY: MemoryBarrier = MemoryBarrier(glow::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    pub const ELEMENT_ARRAY: MemoryBarrier = MemoryBarrier(glow::ELEMENT_ARRAY_BARRIER_BIT);
+    pub const UNIFORM: MemoryBarrier = MemoryBarrier(glow::UNIFORM_BARRIER_BIT);
+    pub const TEXTURE_FETCH: MemoryBarrier = MemoryBarrier(glow::TEXTURE_FETCH_BARRIER_BIT);
+    pub const SHADER_IMAGE_ACCESS: MemoryBarrier = MemoryBarrier(glow::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    pub const COMMAND: MemoryBarrier = MemoryBarrier(glow::COMMAND_BARRIER_BIT);
+    pub const BUFFER_UPDATE: MemoryBarrier = MemoryBarrier(glow::BUFFER_UPDATE_BARRIER_BIT);
+    pub const TEXTURE_UPDATE: MemoryBarrier = MemoryBarrier(glow::TEXTURE_UPDATE_BARRIER_BIT);
+    pub const FRAMEBUFFER: MemoryBarrier = MemoryBarrier(glow::FRAMEBUFFER_BARRIER_BIT);
+    pub const SHADER_STORAGE: MemoryBarrier = MemoryBarrier(glow::SHADER_STORAGE_BARRIER_BIT);
+    pub const ALL: MemoryBarrier = MemoryBarrier(glow::ALL_BARRIER_BITS);
+}
+
+impl std::ops::BitOr for MemoryBarrier {
+    type Output = MemoryBarrier;
+
+    fn bitor(self, other: MemoryBarrier) -> MemoryBarrier {
+        MemoryBarrier(self.0 | other.0)
+    }
+}
+
+/// Returned, boxed, when a program fails to build, `log` being the driver's.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaderError {
+    Compile { stage: ShaderStage, log: String },
+    Link { log: String },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Compile { stage, log } => write!(f, "Failed to compile {:?} shader: {}", stage, log),
+            ShaderError::Link { log } => write!(f, "Failed to link program: {}", log),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// Value of a uniform, matrices being column-major.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Uniform {
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4([f32; 16]),
+}
+
+#[derive(Clone, Copy)]
+pub enum BufferUsage {
+    StaticDraw,
+    DynamicDraw,
+}
+
+impl From<BufferUsage> for u32 {
+    fn from(buffer_usage: BufferUsage) -> u32{
+        match buffer_usage {
+            BufferUsage::StaticDraw => glow::STATIC_DRAW,
+            BufferUsage::DynamicDraw => glow::DYNAMIC_DRAW,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+impl From<FilterMode> for u32 {
+    fn from(filter_mode: FilterMode) -> u32 {
+        match filter_mode {
+            FilterMode::Nearest => glow::NEAREST,
+            FilterMode::Linear => glow::LINEAR,
+        }
+    }
+}
+
+impl From<FilterMode> for i32 {
+    fn from(filter_mode: FilterMode) -> i32 {
+        u32::from(filter_mode) as i32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Triangles,
+    Lines,
+    Points,
+    /// Patches of the given vertex count, for tessellation shaders.
+    Patches(u32),
+}
+
+impl From<Primitive> for u32 {
+    fn from(primitive: Primitive) -> u32 {
+        match primitive {
+            Primitive::Triangles => glow::TRIANGLES,
+            Primitive::Lines => glow::LINES,
+            Primitive::Points => glow::POINTS,
+            Primitive::Patches(_) => glow::PATCHES,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RawVertexBuffer {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: BufferId,
+    count: usize,
+    stride: usize,
+}
+
+handle_impls!(RawVertexBuffer, delete_buffer, Buffer);
+
+impl RawVertexBuffer {
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get_stride(&self) -> usize {
+        self.stride
+    }
+}
+
+#[derive(Debug)]
+pub struct RawIndexBuffer {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: BufferId,
+    count: usize,
+}
+
+handle_impls!(RawIndexBuffer, delete_buffer, Buffer);
+
+impl RawIndexBuffer {
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Buffer bound to uniform or storage block binding points.
+#[derive(Debug)]
+pub struct RawBlockBuffer {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: BufferId,
+    kind: BlockKind,
+    size: usize,
+}
+
+handle_impls!(RawBlockBuffer, delete_buffer, Buffer);
+
+impl RawBlockBuffer {
+    pub fn get_kind(&self) -> BlockKind {
+        self.kind
+    }
+
+    /// Size in bytes.
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Uniform block content shared by programs, see `GraphicsDevice::new_uniform_buffer`.
+#[derive(Debug)]
+pub struct UniformBuffer<T> {
+    raw: RawBlockBuffer,
+    marker: PhantomData<T>,
+}
+
+impl<T> UniformBuffer<T> {
+    pub fn get_raw(&self) -> &RawBlockBuffer {
+        &self.raw
+    }
+}
+
+/// Array of `T` in a storage block, see `GraphicsDevice::new_storage_buffer`.
+#[derive(Debug)]
+pub struct StorageBuffer<T> {
+    raw: RawBlockBuffer,
+    count: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T> StorageBuffer<T> {
+    pub fn get_raw(&self) -> &RawBlockBuffer {
+        &self.raw
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
+/// See `GraphicsDevice::new_stream_buffer`.
+#[derive(Debug)]
+pub struct StreamBuffer {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: BufferId,
+    kind: StreamKind,
+    allocator: StreamAllocator,
+    /// Start of the persistent mapping.
+    mapping: Option<*mut u8>,
+    /// Fence of each frame region, persistent mode only.
+    fences: Vec<Option<FenceId>>,
+}
+
+impl StreamBuffer {
+    unsafe fn delete_fences(&self) {
+        for fence in self.fences.iter().flatten() {
+            self.gl.delete_sync(*fence);
+        }
+    }
+
+    pub fn get_kind(&self) -> StreamKind {
+        self.kind
+    }
+
+    pub fn get_mode(&self) -> StreamMode {
+        match self.mapping {
+            Some(_) => StreamMode::Persistent,
+            None => StreamMode::Orphaning,
+        }
+    }
+
+    /// Bytes available each frame.
+    pub fn get_frame_size(&self) -> usize {
+        self.allocator.get_frame_size()
+    }
+
+    /// Bytes used in the current frame.
+    pub fn get_used(&self) -> usize {
+        self.allocator.get_used()
+    }
+}
+
+// deleting the buffer unmaps it
+handle_impls!(StreamBuffer, delete_buffer, Buffer, delete_fences);
+
+#[derive(Debug)]
 pub struct RawProgram {
-    id: ProgramId
-}
\ No newline at end of file
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: ProgramId,
+}
+
+handle_impls!(RawProgram, delete_program, Program);
+
+#[derive(Debug)]
+pub struct RawTexture {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: TextureId,
+    width: i32,
+    height: i32,
+}
+
+handle_impls!(RawTexture, delete_texture, Texture);
+
+impl RawTexture {
+    pub fn get_width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.height
+    }
+}
+
+#[derive(Debug)]
+pub struct RawVertexArray {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: VertexArrayId,
+}
+
+handle_impls!(RawVertexArray, delete_vertex_array, VertexArray);
+
+#[derive(Debug)]
+pub struct RawRenderTarget {
+    gl: Rc<GlowContext>,
+    deleted: DeletedHandles,
+    id: FramebufferId,
+    texture: RawTexture,
+}
+
+handle_impls!(RawRenderTarget, delete_framebuffer, Framebuffer);
+
+impl RawRenderTarget {
+    pub fn get_texture(&self) -> &RawTexture {
+        &self.texture
+    }
+
+    pub fn get_width(&self) -> i32 {
+        self.texture.width
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.texture.height
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use super::{BindingCache, DeletedHandle, GlVersion, ProgramBuilder, ShaderStage};
+    use crate::block::BlockKind;
+
+    #[test]
+    fn parse_gl_version_strings() {
+        assert_eq!(GlVersion::parse("4.6.0 NVIDIA 440.44"), Some(GlVersion::gl(4, 6)));
+        assert_eq!(GlVersion::parse("3.3 (Core Profile) Mesa 19.2.8"), Some(GlVersion::gl(3, 3)));
+        assert_eq!(GlVersion::parse("OpenGL ES 3.0 Mesa 19.2.8"), Some(GlVersion::gles(3, 0)));
+        assert_eq!(GlVersion::parse("garbage"), None);
+    }
+
+    #[test]
+    fn features_follow_version() {
+        assert!(GlVersion::gl(3, 0).supports_vertex_arrays());
+        assert!(!GlVersion::gl(2, 1).supports_vertex_arrays());
+        assert!(!GlVersion::gles(2, 0).supports_vertex_arrays());
+        assert!(!GlVersion::gles(2, 0).supports_framebuffer_blit());
+    }
+
+    #[test]
+    fn deleted_handles_leave_the_cache() {
+        let mut cache = BindingCache {
+            current_vertex_buffer: Some(1),
+            current_index_buffer: Some(2),
+            current_program: Some(3),
+            current_vertex_array: Some(4),
+            current_framebuffer: None,
+            active_texture_unit: 1,
+            textures: HashMap::new(),
+            index_buffers: HashMap::new(),
+            default_vertex_array: Some(4),
+            viewport: (0, 0, 0, 0),
+            alpha_blending: false,
+            wireframe: false,
+            patch_vertices: None,
+        };
+        cache.index_buffers.insert(6, 1);
+        cache.textures.insert(0, Some(5));
+        cache.textures.insert(1, Some(7));
+
+        cache.forget(DeletedHandle::Buffer(1));
+        cache.forget(DeletedHandle::Program(3));
+        cache.forget(DeletedHandle::Texture(7));
+        assert_eq!(cache.current_vertex_buffer, None);
+        assert_eq!(cache.current_index_buffer, Some(2));
+        assert!(cache.index_buffers.is_empty());
+        assert_eq!(cache.current_program, None);
+        assert_eq!(cache.textures.get(&0), Some(&Some(5)));
+        assert_eq!(cache.textures.get(&1), None);
+
+        cache.forget(DeletedHandle::VertexArray(4));
+        assert_eq!((cache.current_vertex_array, cache.current_index_buffer), (None, None));
+    }
+
+    #[test]
+    fn stages_follow_version() {
+        use ShaderStage::*;
+
+        assert!(GlVersion::gl(3, 3).supports_stage(Geometry));
+        assert!(!GlVersion::gl(3, 3).supports_stage(TessControl));
+        assert!(!GlVersion::gl(4, 2).supports_stage(Compute));
+        assert!(GlVersion::gles(3, 1).supports_stage(Compute));
+        assert!(!GlVersion::gles(3, 1).supports_stage(Geometry));
+
+        let mut builder = ProgramBuilder::new();
+        builder.with_vertex("a").with_tess_control("b").with_fragment("c").with_vertex("d");
+        assert_eq!(builder.get_stages(), vec![TessControl, Fragment, Vertex]);
+        assert!(GlVersion::gl(4, 6).check_stages(&builder.get_stages()).is_err());
+
+        builder.with_tess_evaluation("e");
+        assert!(GlVersion::gl(4, 6).check_stages(&builder.get_stages()).is_ok());
+        assert!(GlVersion::gl(3, 3).check_stages(&builder.get_stages()).is_err());
+        assert!(GlVersion::gl(4, 6).check_stages(&[Fragment]).is_err());
+        assert!(GlVersion::gl(4, 6).check_stages(&[Vertex, Compute]).is_err());
+
+        assert!(GlVersion::gl(3, 3).supports_block(BlockKind::Uniform));
+        assert!(!GlVersion::gl(3, 3).supports_block(BlockKind::Storage));
+        assert!(GlVersion::gles(3, 0).supports_block(BlockKind::Uniform));
+        assert!(!GlVersion::gles(2, 0).supports_block(BlockKind::Uniform));
+    }
+
+}
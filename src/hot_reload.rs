@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{error, info};
+
+use crate::gl::{GraphicsDevice, RawProgram, ShaderError, ShaderStage};
+use crate::Result;
+
+/// Detects changes of files by polling their modification time and size.
+#[derive(Debug, Clone)]
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new<P>(paths: &[P]) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            files: paths
+                .iter()
+                .map(|path| (path.as_ref().to_path_buf(), stamp(path.as_ref())))
+                .collect(),
+        }
+    }
+
+    pub fn get_paths(&self) -> Vec<&Path> {
+        self.files.iter().map(|(path, _)| path.as_path()).collect()
+    }
+
+    /// Whether a file changed, appeared or disappeared since the last call.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+            if current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadEvent {
+    Unchanged,
+    Reloaded,
+    /// The previous value is kept, see `HotReload::get_last_error`.
+    Failed,
+}
+
+/// Value loaded from watched files, replaced only when loading it again succeeds.
+pub struct HotReload<T> {
+    watcher: FileWatcher,
+    value: T,
+    last_error: Option<Box<dyn std::error::Error>>,
+}
+
+impl<T> HotReload<T> {
+    pub fn new(watcher: FileWatcher, value: T) -> Self {
+        Self {
+            watcher,
+            value,
+            last_error: None,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_paths(&self) -> Vec<&Path> {
+        self.watcher.get_paths()
+    }
+
+    /// Error of the last failed reload, cleared by a successful one.
+    pub fn get_last_error(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.last_error.as_deref()
+    }
+
+    /// Calls `load` if a file changed since the last poll.
+    pub fn poll<F>(&mut self, load: F) -> ReloadEvent
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        if !self.watcher.poll() {
+            return ReloadEvent::Unchanged;
+        }
+
+        match load() {
+            Ok(value) => {
+                self.value = value;
+                self.last_error = None;
+                ReloadEvent::Reloaded
+            }
+            Err(err) => {
+                self.last_error = Some(err);
+                ReloadEvent::Failed
+            }
+        }
+    }
+}
+
+/// Program built from a vertex and a fragment file, rebuilt when they change
+/// on disk. A failed rebuild keeps the previous program.
+pub struct ShaderAsset {
+    vertex_path: PathBuf,
+    fragment_path: PathBuf,
+    program: HotReload<RawProgram>,
+}
+
+impl ShaderAsset {
+    pub fn load<P, Q>(device: &mut GraphicsDevice, vertex_path: P, fragment_path: Q) -> Result<ShaderAsset>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let vertex_path = vertex_path.as_ref().to_path_buf();
+        let fragment_path = fragment_path.as_ref().to_path_buf();
+        let watcher = FileWatcher::new(&[&vertex_path, &fragment_path]);
+
+        let program = build(device, &vertex_path, &fragment_path)?;
+        info!("Loaded shader {:?} {:?}", vertex_path, fragment_path);
+
+        Ok(ShaderAsset {
+            vertex_path,
+            fragment_path,
+            program: HotReload::new(watcher, program),
+        })
+    }
+
+    pub fn get_program(&self) -> &RawProgram {
+        self.program.get()
+    }
+
+    pub fn get_last_error(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.program.get_last_error()
+    }
+
+    /// Rebuilds the program if a file changed, to call once per frame or so.
+    pub fn poll(&mut self, device: &mut GraphicsDevice) -> ReloadEvent {
+        let (vertex_path, fragment_path) = (&self.vertex_path, &self.fragment_path);
+        let event = self.program.poll(|| build(device, vertex_path, fragment_path));
+
+        match event {
+            ReloadEvent::Reloaded => info!("Reloaded shader {:?} {:?}", vertex_path, fragment_path),
+            ReloadEvent::Failed => self.log_error(),
+            ReloadEvent::Unchanged => (),
+        }
+        event
+    }
+
+    fn log_error(&self) {
+        let err = match self.program.get_last_error() {
+            Some(err) => err,
+            None => return,
+        };
+
+        match err.downcast_ref::<ShaderError>() {
+            Some(ShaderError::Compile { stage, log }) => {
+                let path = match stage {
+                    ShaderStage::Vertex => &self.vertex_path,
+                    _ => &self.fragment_path,
+                };
+                error!("Shader reload failed, keeping the previous program. file={:?} stage={:?}", path, stage);
+                for line in log.lines() {
+                    error!("    {}", line);
+                }
+            }
+            Some(ShaderError::Link { log }) => {
+                error!(
+                    "Shader reload failed, keeping the previous program. files={:?} {:?} stage=link",
+                    self.vertex_path, self.fragment_path
+                );
+                for line in log.lines() {
+                    error!("    {}", line);
+                }
+            }
+            None => error!("Shader reload failed, keeping the previous program: {}", err),
+        }
+    }
+}
+
+fn build(device: &mut GraphicsDevice, vertex_path: &Path, fragment_path: &Path) -> Result<RawProgram> {
+    let vertex = fs::read_to_string(vertex_path)?;
+    let fragment = fs::read_to_string(fragment_path)?;
+    device.new_program(&vertex, &fragment)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{FileWatcher, HotReload, ReloadEvent};
+    use std::fs;
+
+    #[test]
+    fn reloads_on_change_and_keeps_value_on_failure() {
+        let dir = std::env::temp_dir().join(format!("ckrl-hot-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.glsl");
+        fs::write(&path, "void main() {}").unwrap();
+
+        let load = || -> crate::Result<String> {
+            let source = fs::read_to_string(&path)?;
+            if source.contains("error") {
+                return Err(failure::err_msg("0:1(1): error: syntax error").into());
+            }
+            Ok(source)
+        };
+
+        let mut asset = HotReload::new(FileWatcher::new(&[&path]), load().unwrap());
+        assert_eq!(asset.poll(load), ReloadEvent::Unchanged);
+
+        fs::write(&path, "void main() { error }").unwrap();
+        assert_eq!(asset.poll(load), ReloadEvent::Failed);
+        assert_eq!(asset.get(), "void main() {}");
+        assert!(asset.get_last_error().unwrap().to_string().contains("syntax error"));
+
+        fs::write(&path, "void main() { discard; }").unwrap();
+        assert_eq!(asset.poll(load), ReloadEvent::Reloaded);
+        assert_eq!(asset.get(), "void main() { discard; }");
+        assert!(asset.get_last_error().is_none());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(asset.poll(load), ReloadEvent::Failed);
+        assert_eq!(asset.get(), "void main() { discard; }");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
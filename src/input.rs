@@ -0,0 +1,445 @@
+use std::collections::HashSet;
+
+use glutin::dpi::{LogicalPosition, PhysicalPosition};
+use glutin::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
+    MouseScrollDelta, ScanCode, VirtualKeyCode, WindowEvent,
+};
+
+use crate::action::{Bindings, Button};
+
+/// Number of pixels a single line of `MouseScrollDelta::LineDelta` counts for.
+const PIXELS_PER_LINE: f64 = 20.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextEvent {
+    /// Committed text, typed or coming from an input method.
+    ///
+    /// The windowing backend doesn't report IME composition (preedit), only the
+    /// text once the user commits it.
+    Text(String),
+    Backspace,
+    Delete,
+    Enter,
+}
+
+/// Keyboard and mouse state built from the window events.
+///
+/// `*_pressed` and `*_released` queries only hold for the frame in which the
+/// change happened, a frame starting on every `Event::NewEvents`.
+pub struct Input {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+
+    scancodes_down: HashSet<ScanCode>,
+    scancodes_pressed: HashSet<ScanCode>,
+    scancodes_released: HashSet<ScanCode>,
+
+    modifiers: ModifiersState,
+
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+
+    mouse_position: PhysicalPosition<f64>,
+    wheel_delta: (f64, f64),
+    cursor_inside: bool,
+    scale_factor: f64,
+
+    bindings: Bindings,
+
+    text_input_enabled: bool,
+    text_events: Vec<TextEvent>,
+}
+
+impl Input {
+    pub fn new(scale_factor: f64) -> Input {
+        Input {
+            keys_down: HashSet::new(),
+            keys_pressed: HashSet::new(),
+            keys_released: HashSet::new(),
+
+            scancodes_down: HashSet::new(),
+            scancodes_pressed: HashSet::new(),
+            scancodes_released: HashSet::new(),
+
+            modifiers: ModifiersState::default(),
+
+            buttons_down: HashSet::new(),
+            buttons_pressed: HashSet::new(),
+            buttons_released: HashSet::new(),
+
+            mouse_position: PhysicalPosition::new(0.0, 0.0),
+            wheel_delta: (0.0, 0.0),
+            cursor_inside: false,
+            scale_factor,
+
+            bindings: Bindings::new(),
+
+            text_input_enabled: false,
+            text_events: Vec::new(),
+        }
+    }
+
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        match event {
+            Event::NewEvents(_) => self.begin_frame(),
+            Event::WindowEvent { event, .. } => self.handle_window_event(event),
+            Event::DeviceEvent { event: DeviceEvent::ModifiersChanged(modifiers), .. } => {
+                self.modifiers = *modifiers;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => self.handle_keyboard_input(input),
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    if self.buttons_down.insert(*button) {
+                        self.buttons_pressed.insert(*button);
+                    }
+                }
+                ElementState::Released => {
+                    if self.buttons_down.remove(button) {
+                        self.buttons_released.insert(*button);
+                    }
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse_position = PhysicalPosition::new(position.x as f64, position.y as f64);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x as f64, *y as f64),
+                    MouseScrollDelta::PixelDelta(position) => {
+                        (position.x / PIXELS_PER_LINE, position.y / PIXELS_PER_LINE)
+                    }
+                };
+                self.wheel_delta.0 += x;
+                self.wheel_delta.1 += y;
+            }
+            WindowEvent::ReceivedCharacter(c) if self.text_input_enabled => {
+                self.handle_character(*c)
+            }
+            WindowEvent::CursorEntered { .. } => self.cursor_inside = true,
+            WindowEvent::CursorLeft { .. } => self.cursor_inside = false,
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.scale_factor = *scale_factor;
+            }
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => (),
+        }
+    }
+
+    fn handle_keyboard_input(&mut self, input: &KeyboardInput) {
+        match input.state {
+            ElementState::Pressed => {
+                if self.scancodes_down.insert(input.scancode) {
+                    self.scancodes_pressed.insert(input.scancode);
+                }
+                if let Some(key) = input.virtual_keycode {
+                    if self.keys_down.insert(key) {
+                        self.keys_pressed.insert(key);
+                    }
+                }
+            }
+            ElementState::Released => {
+                if self.scancodes_down.remove(&input.scancode) {
+                    self.scancodes_released.insert(input.scancode);
+                }
+                if let Some(key) = input.virtual_keycode {
+                    if self.keys_down.remove(&key) {
+                        self.keys_released.insert(key);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_character(&mut self, c: char) {
+        let event = match c {
+            '\u{8}' => TextEvent::Backspace,
+            '\u{7f}' => TextEvent::Delete,
+            '\r' | '\n' => TextEvent::Enter,
+            c if c.is_control() && c != '\t' => return,
+            c => {
+                if let Some(TextEvent::Text(text)) = self.text_events.last_mut() {
+                    text.push(c);
+                } else {
+                    self.text_events.push(TextEvent::Text(c.to_string()));
+                }
+                return;
+            }
+        };
+        self.text_events.push(event);
+    }
+
+    /// Clears the per-frame state, called when a new batch of events starts.
+    pub fn begin_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.scancodes_pressed.clear();
+        self.scancodes_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.wheel_delta = (0.0, 0.0);
+        self.text_events.clear();
+    }
+
+    /// Releases everything held, the window won't see the key ups once it lost focus.
+    fn release_all(&mut self) {
+        self.keys_released.extend(self.keys_down.drain());
+        self.scancodes_released.extend(self.scancodes_down.drain());
+        self.buttons_released.extend(self.buttons_down.drain());
+        self.modifiers = ModifiersState::default();
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_up(&self, key: VirtualKeyCode) -> bool {
+        !self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: VirtualKeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn is_scancode_down(&self, scancode: ScanCode) -> bool {
+        self.scancodes_down.contains(&scancode)
+    }
+
+    pub fn is_scancode_pressed(&self, scancode: ScanCode) -> bool {
+        self.scancodes_pressed.contains(&scancode)
+    }
+
+    pub fn is_scancode_released(&self, scancode: ScanCode) -> bool {
+        self.scancodes_released.contains(&scancode)
+    }
+
+    pub fn get_keys_down(&self) -> impl Iterator<Item = &VirtualKeyCode> {
+        self.keys_down.iter()
+    }
+
+    pub fn get_modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_mouse_button_up(&self, button: MouseButton) -> bool {
+        !self.buttons_down.contains(&button)
+    }
+
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_mouse_button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    pub fn get_mouse_position(&self) -> PhysicalPosition<f64> {
+        self.mouse_position
+    }
+
+    pub fn get_mouse_position_logical(&self) -> LogicalPosition<f64> {
+        self.mouse_position.to_logical(self.scale_factor)
+    }
+
+    /// Wheel movement accumulated this frame, in lines.
+    pub fn get_wheel_delta(&self) -> (f64, f64) {
+        self.wheel_delta
+    }
+
+    pub fn is_cursor_inside(&self) -> bool {
+        self.cursor_inside
+    }
+
+    pub fn get_scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn is_button_down(&self, button: Button) -> bool {
+        match button {
+            Button::Key(key) => self.is_key_down(key),
+            Button::ScanCode(scancode) => self.is_scancode_down(scancode),
+            Button::Mouse(button) => self.is_mouse_button_down(button),
+        }
+    }
+
+    pub fn is_button_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::Key(key) => self.is_key_pressed(key),
+            Button::ScanCode(scancode) => self.is_scancode_pressed(scancode),
+            Button::Mouse(button) => self.is_mouse_button_pressed(button),
+        }
+    }
+
+    pub fn is_button_released(&self, button: Button) -> bool {
+        match button {
+            Button::Key(key) => self.is_key_released(key),
+            Button::ScanCode(scancode) => self.is_scancode_released(scancode),
+            Button::Mouse(button) => self.is_mouse_button_released(button),
+        }
+    }
+
+    /// Starts collecting text events, e.g. while a chat box has the focus.
+    pub fn start_text_input(&mut self) {
+        self.text_input_enabled = true;
+    }
+
+    pub fn stop_text_input(&mut self) {
+        self.text_input_enabled = false;
+        self.text_events.clear();
+    }
+
+    pub fn is_text_input_enabled(&self) -> bool {
+        self.text_input_enabled
+    }
+
+    /// Text events received this frame while text input is enabled.
+    pub fn get_text_events(&self) -> &[TextEvent] {
+        &self.text_events
+    }
+
+    pub fn get_bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn get_bindings_mut(&mut self) -> &mut Bindings {
+        &mut self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+
+    pub fn is_action_down(&self, action: &str) -> bool {
+        self.bindings.is_action_down(self, action)
+    }
+
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        self.bindings.is_action_pressed(self, action)
+    }
+
+    pub fn is_action_released(&self, action: &str) -> bool {
+        self.bindings.is_action_released(self, action)
+    }
+
+    pub fn get_axis(&self, axis: &str) -> f32 {
+        self.bindings.get_axis_value(self, axis)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Input, TextEvent};
+    use crate::action::Button;
+    use glutin::event::{DeviceId, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+    #[allow(deprecated)]
+    fn key(state: ElementState, scancode: u32, key: VirtualKeyCode) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn key_pressed_only_lasts_one_frame() {
+        let mut input = Input::new(1.0);
+
+        input.handle_window_event(&key(ElementState::Pressed, 30, VirtualKeyCode::A));
+        assert!(input.is_key_down(VirtualKeyCode::A));
+        assert!(input.is_key_pressed(VirtualKeyCode::A));
+        assert!(input.is_scancode_pressed(30));
+
+        input.begin_frame();
+        input.handle_window_event(&key(ElementState::Pressed, 30, VirtualKeyCode::A));
+        assert!(input.is_key_down(VirtualKeyCode::A));
+        assert!(!input.is_key_pressed(VirtualKeyCode::A));
+
+        input.begin_frame();
+        input.handle_window_event(&key(ElementState::Released, 30, VirtualKeyCode::A));
+        assert!(input.is_key_up(VirtualKeyCode::A));
+        assert!(input.is_key_released(VirtualKeyCode::A));
+        assert!(!input.is_scancode_down(30));
+    }
+
+    #[test]
+    fn focus_loss_releases_keys() {
+        let mut input = Input::new(1.0);
+
+        input.handle_window_event(&key(ElementState::Pressed, 17, VirtualKeyCode::W));
+        input.begin_frame();
+        input.handle_window_event(&WindowEvent::Focused(false));
+
+        assert!(!input.is_key_down(VirtualKeyCode::W));
+        assert!(input.is_key_released(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn chord_action_needs_every_button() {
+        let mut input = Input::new(1.0);
+        input.get_bindings_mut().bind_action(
+            "save",
+            vec![Button::Key(VirtualKeyCode::LControl), Button::Key(VirtualKeyCode::S)],
+        );
+
+        input.handle_window_event(&key(ElementState::Pressed, 83, VirtualKeyCode::S));
+        assert!(!input.is_action_down("save"));
+
+        input.begin_frame();
+        input.handle_window_event(&key(ElementState::Pressed, 29, VirtualKeyCode::LControl));
+        assert!(input.is_action_down("save"));
+        assert!(input.is_action_pressed("save"));
+
+        input.begin_frame();
+        input.handle_window_event(&key(ElementState::Released, 83, VirtualKeyCode::S));
+        assert!(!input.is_action_down("save"));
+        assert!(input.is_action_released("save"));
+    }
+
+    #[test]
+    fn text_input_groups_characters() {
+        let mut input = Input::new(1.0);
+
+        input.handle_window_event(&WindowEvent::ReceivedCharacter('x'));
+        assert!(input.get_text_events().is_empty());
+
+        input.start_text_input();
+        for c in "hé\u{8}y\r".chars() {
+            input.handle_window_event(&WindowEvent::ReceivedCharacter(c));
+        }
+
+        assert_eq!(input.get_text_events(), &[
+            TextEvent::Text("hé".into()),
+            TextEvent::Backspace,
+            TextEvent::Text("y".into()),
+            TextEvent::Enter,
+        ]);
+
+        input.begin_frame();
+        assert!(input.get_text_events().is_empty());
+    }
+
+}
@@ -1,10 +1,36 @@
+#[cfg(feature = "window")]
 pub mod window;
+#[cfg(feature = "window")]
+pub mod monitor;
+#[cfg(feature = "window")]
 pub mod context;
 pub mod logger;
 pub mod gl;
+pub mod block;
+pub mod stream;
+#[cfg(feature = "window")]
+pub mod input;
+#[cfg(feature = "window")]
+pub mod action;
+pub mod scaler;
+pub mod console;
+pub mod cvar;
+pub mod command;
 pub mod shader_string;
+pub mod preprocessor;
+pub mod variant;
+pub mod program_cache;
+pub mod hot_reload;
+pub mod reflect;
+#[cfg(feature = "cross")]
+pub mod cross;
 
-pub use self::logger::start_logger;
-pub use self::shader_string::{FRAGMENT_SHADER, VERTEX_SHADER};
+pub use self::logger::{install_crash_handler, start_logger, LogBuffer, LogRecord, LoggerBuilder};
+pub use self::shader_string::{BuiltinShader, ShaderDialect};
+#[cfg(feature = "macros")]
+pub use ckrl_macros::{include_shader, shader};
+
+// lets the macros' `::ckrl::` paths resolve inside this crate
+extern crate self as ckrl;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
\ No newline at end of file
@@ -1,26 +1,417 @@
-use std::io::Write;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
+use env_logger::filter::{Builder as FilterBuilder, Filter};
+use log::{Level, LevelFilter, Log, Metadata, Record};
 
+use crate::Result;
+
+
+/// Starts the default logger: debug level, overridable through `RUST_LOG`.
+/// Does nothing if a logger is already set.
 pub fn start_logger() {
-    env_logger::Builder::new()
-        .format(|buf, record| {
-            writeln!(buf,
-                "{} [{}] - {}",
-                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                record.level(),
+    let _ = LoggerBuilder::new().try_init();
+}
+
+
+#[derive(Debug)]
+pub struct LoggerBuilder {
+    level: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+    env: Option<String>,
+    colors: bool,
+    stderr: bool,
+    thread_names: bool,
+    file: Option<FileOutput>,
+    buffer: Option<LogBuffer>,
+}
+
+#[derive(Debug, Clone)]
+struct FileOutput {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LoggerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_level(&mut self, level: LevelFilter) -> &mut Self {
+        self.level = level;
+        self
+    }
+
+    /// Level of the records from `module` and its submodules, e.g. `("ckrl::gl", Warn)`.
+    pub fn with_module_level<S>(&mut self, module: S, level: LevelFilter) -> &mut Self
+    where
+        S: ToString,
+    {
+        self.modules.push((module.to_string(), level));
+        self
+    }
+
+    /// Environment variable holding `RUST_LOG` style directives, applied over
+    /// the levels set in code. `RUST_LOG` by default, `None` to ignore the environment.
+    pub fn with_env(&mut self, env: Option<&str>) -> &mut Self {
+        self.env = env.map(|env| env.to_string());
+        self
+    }
+
+    pub fn with_colors(&mut self, colors: bool) -> &mut Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_stderr(&mut self, stderr: bool) -> &mut Self {
+        self.stderr = stderr;
+        self
+    }
+
+    pub fn with_thread_names(&mut self, thread_names: bool) -> &mut Self {
+        self.thread_names = thread_names;
+        self
+    }
+
+    /// Also writes to `path`, moving it to `path.1` once it reaches `max_size`
+    /// bytes and keeping up to `max_files` old files.
+    pub fn with_file<P>(&mut self, path: P, max_size: u64, max_files: usize) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.file = Some(FileOutput {
+            path: path.as_ref().to_path_buf(),
+            max_size,
+            max_files,
+        });
+        self
+    }
+
+    /// Also keeps the records in `buffer`, e.g. to show them in a `LogConsole`.
+    pub fn with_buffer(&mut self, buffer: LogBuffer) -> &mut Self {
+        self.buffer = Some(buffer);
+        self
+    }
+
+    pub fn build(&self) -> Result<Logger> {
+        let mut filter = FilterBuilder::new();
+        filter.filter_level(self.level);
+        for (module, level) in &self.modules {
+            filter.filter_module(module, *level);
+        }
+        if let Some(directives) = self.env.as_ref().and_then(|env| std::env::var(env).ok()) {
+            filter.parse(&directives);
+        }
+
+        let file = match &self.file {
+            Some(output) => Some(Mutex::new(RotatingFile::open(output.clone())?)),
+            None => None,
+        };
+
+        Ok(Logger {
+            filter: filter.build(),
+            colors: self.colors,
+            stderr: self.stderr,
+            thread_names: self.thread_names,
+            file,
+            buffer: self.buffer.clone(),
+        })
+    }
+
+    /// Sets the global logger, failing if one is already set.
+    pub fn try_init(&self) -> Result<()> {
+        let logger = self.build()?;
+        let max_level = logger.filter.filter();
+
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    /// Same as `try_init`, panicking on failure.
+    pub fn init(&self) {
+        self.try_init().expect("Failed to set the logger");
+    }
+}
+
+impl Default for LoggerBuilder {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Debug,
+            modules: Vec::new(),
+            env: Some("RUST_LOG".into()),
+            colors: false,
+            stderr: true,
+            thread_names: true,
+            file: None,
+            buffer: None,
+        }
+    }
+}
+
+
+pub struct Logger {
+    filter: Filter,
+    colors: bool,
+    stderr: bool,
+    thread_names: bool,
+    file: Option<Mutex<RotatingFile>>,
+    buffer: Option<LogBuffer>,
+}
+
+impl Logger {
+    fn format(&self, record: &Record, colors: bool) -> String {
+        let level = if colors {
+            format!("\x1b[{}m{}\x1b[0m", level_color(record.level()), record.level())
+        } else {
+            record.level().to_string()
+        };
+
+        let timestamp = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f");
+
+        if self.thread_names {
+            let thread = thread::current();
+            format!(
+                "{} [{}] ({}) - {}\n",
+                timestamp,
+                level,
+                thread.name().unwrap_or("unnamed"),
                 record.args()
             )
-        })
-        .filter(None, log::LevelFilter::Debug)
-        .init();
+        } else {
+            format!("{} [{}] - {}\n", timestamp, level, record.args())
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        if self.stderr {
+            let _ = io::stderr().write_all(self.format(record, self.colors).as_bytes());
+        }
+
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write(self.format(record, false).as_bytes());
+            }
+        }
+
+        if let Some(buffer) = &self.buffer {
+            buffer.push(LogRecord {
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+                timestamp: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+                thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+fn level_color(level: Level) -> u8 {
+    match level {
+        Level::Error => 31,
+        Level::Warn => 33,
+        Level::Info => 32,
+        Level::Debug => 36,
+        Level::Trace => 90,
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub timestamp: String,
+    pub thread: String,
+}
+
+impl LogRecord {
+    pub fn to_line(&self) -> String {
+        format!("{} [{}] ({}) - {}", self.timestamp, self.level, self.thread, self.message)
+    }
+}
+
+/// Keeps the last `capacity` records in memory, clones share the same records.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, record: LogRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut records) = self.records.lock() {
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+
+    /// Copy of the records, oldest first.
+    pub fn get_records(&self) -> Vec<LogRecord> {
+        match self.records.lock() {
+            Ok(records) => records.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().map(|records| records.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut records) = self.records.lock() {
+            records.clear();
+        }
+    }
+}
+
+
+/// Installs a panic hook writing the panic, `device_info` (see
+/// `GraphicsDevice::get_info`) and the records of `buffer` to `path`.
+/// The previous hook still runs afterwards.
+pub fn install_crash_handler<P>(path: P, buffer: LogBuffer, device_info: String)
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref().to_path_buf();
+    let previous = panic::take_hook();
+
+    panic::set_hook(Box::new(move |panic_info| {
+        let report = crash_report(&panic_info.to_string(), &device_info, &buffer);
+        if let Err(err) = fs::write(&path, report) {
+            let _ = writeln!(io::stderr(), "Failed to write crash report to {:?}: {}", path, err);
+        } else {
+            let _ = writeln!(io::stderr(), "Crash report written to {:?}", path);
+        }
+
+        previous(panic_info);
+    }));
+}
+
+fn crash_report(panic: &str, device_info: &str, buffer: &LogBuffer) -> String {
+    let mut report = format!(
+        "Crash at {}\n{}\n\n{}\nLast {} log records:\n",
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+        panic,
+        device_info,
+        buffer.len(),
+    );
+    for record in buffer.get_records() {
+        report.push_str(&record.to_line());
+        report.push('\n');
+    }
+    report
+}
+
+
+struct RotatingFile {
+    output: FileOutput,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(output: FileOutput) -> Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&output.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile { output, file, size })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + bytes.len() as u64 > self.output.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.output.max_files == 0 {
+            self.file = File::create(&self.output.path)?;
+        } else {
+            let oldest = self.rotated_path(self.output.max_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for index in (1..self.output.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.output.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.output.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.output.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
 }
 
 
 #[cfg(test)]
-mod tests { 
+mod tests {
 
-    use super::start_logger;
-    use log::{debug, info, warn, error};
+    use super::{crash_report, start_logger, FileOutput, LogBuffer, LogRecord, LoggerBuilder, RotatingFile};
+    use log::{debug, info, warn, error, Level, Log, Record};
+    use std::fs;
 
     #[test]
     fn make_logger() {
@@ -29,7 +420,7 @@ mod tests {
         warn!("warn");
         info!("info");
         debug!("debug");
-    
+
         info!("such information");
         info!("such information");
         warn!("o_O");
@@ -40,4 +431,77 @@ mod tests {
         debug!("deboogging");
     }
 
+    #[test]
+    fn second_init_fails_without_panic() {
+        start_logger();
+        assert!(LoggerBuilder::new().try_init().is_err());
+    }
+
+    #[test]
+    fn file_rotates_past_max_size() {
+        let dir = std::env::temp_dir().join(format!("ckrl-logger-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ckrl.log");
+
+        let mut file = RotatingFile::open(FileOutput {
+            path: path.clone(),
+            max_size: 10,
+            max_files: 2,
+        })
+        .unwrap();
+
+        for line in &["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(dir.join("ckrl.log.1")).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(dir.join("ckrl.log.2")).unwrap(), "bbbbbbbb\n");
+        assert!(!dir.join("ckrl.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::Info,
+            target: "ckrl".into(),
+            message: message.into(),
+            timestamp: "00:00:00.000".into(),
+            thread: "main".into(),
+        }
+    }
+
+    #[test]
+    fn buffer_keeps_the_last_records() {
+        let buffer = LogBuffer::new(2);
+        for message in &["a", "b", "c"] {
+            buffer.push(record(message));
+        }
+
+        let messages: Vec<_> = buffer.get_records().into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn logger_fills_buffer_and_report() {
+        let buffer = LogBuffer::new(8);
+        let logger = LoggerBuilder::new()
+            .with_env(None)
+            .with_stderr(false)
+            .with_buffer(buffer.clone())
+            .build()
+            .unwrap();
+
+        logger.log(&Record::builder().level(Level::Warn).args(format_args!("low on memory")).build());
+        logger.log(&Record::builder().level(Level::Trace).args(format_args!("filtered")).build());
+        assert_eq!(buffer.len(), 1);
+
+        let report = crash_report("panicked at 'boom'", "Renderer: test\n", &buffer);
+        assert!(report.contains("panicked at 'boom'"));
+        assert!(report.contains("Renderer: test"));
+        assert!(report.contains("[WARN]"));
+        assert!(report.contains("- low on memory"));
+    }
+
 }
@@ -0,0 +1,119 @@
+use glutin::monitor::{MonitorHandle, VideoMode as GlutinVideoMode};
+use glutin::window::Fullscreen;
+
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FullscreenMode {
+    Windowed,
+    /// Covers the monitor with a borderless window, keeping the desktop video mode.
+    Borderless,
+    /// Switches the monitor to the closest supported video mode.
+    Exclusive {
+        width: u32,
+        height: u32,
+        refresh_rate: Option<u16>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    handle: MonitorHandle,
+}
+
+impl Monitor {
+    pub(crate) fn new(handle: MonitorHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn get_name(&self) -> Option<String> {
+        self.handle.name()
+    }
+
+    /// Top-left corner on the desktop, in physical pixels.
+    pub fn get_position(&self) -> (i32, i32) {
+        let position = self.handle.position();
+        (position.x, position.y)
+    }
+
+    /// Size in physical pixels.
+    pub fn get_size(&self) -> (u32, u32) {
+        let size = self.handle.size();
+        (size.width, size.height)
+    }
+
+    pub fn get_scale_factor(&self) -> f64 {
+        self.handle.scale_factor()
+    }
+
+    pub fn get_video_modes(&self) -> Vec<VideoMode> {
+        self.handle.video_modes().map(VideoMode::new).collect()
+    }
+
+    /// Supported video mode closest to the requested size and refresh rate.
+    pub fn find_video_mode(
+        &self,
+        width: u32,
+        height: u32,
+        refresh_rate: Option<u16>,
+    ) -> Option<VideoMode> {
+        let distance = |mode: &VideoMode| {
+            let (w, h) = mode.get_size();
+            let size = (w as i64 - width as i64).abs() + (h as i64 - height as i64).abs();
+            let refresh = match refresh_rate {
+                Some(rate) => (mode.get_refresh_rate() as i64 - rate as i64).abs(),
+                None => -(mode.get_refresh_rate() as i64),
+            };
+            (size, refresh, -(mode.get_bit_depth() as i64))
+        };
+
+        self.get_video_modes().into_iter().min_by_key(distance)
+    }
+
+    pub(crate) fn to_fullscreen(&self, mode: FullscreenMode) -> Result<Option<Fullscreen>> {
+        match mode {
+            FullscreenMode::Windowed => Ok(None),
+            FullscreenMode::Borderless => Ok(Some(Fullscreen::Borderless(self.handle.clone()))),
+            FullscreenMode::Exclusive { width, height, refresh_rate } => {
+                match self.find_video_mode(width, height, refresh_rate) {
+                    Some(video_mode) => Ok(Some(Fullscreen::Exclusive(video_mode.mode))),
+                    None => Err(failure::err_msg(format!(
+                        "No video mode available on monitor {:?}",
+                        self.get_name()
+                    ))
+                    .into()),
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for Monitor {
+    fn eq(&self, other: &Monitor) -> bool {
+        self.handle == other.handle
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoMode {
+    mode: GlutinVideoMode,
+}
+
+impl VideoMode {
+    fn new(mode: GlutinVideoMode) -> Self {
+        Self { mode }
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        let size = self.mode.size();
+        (size.width, size.height)
+    }
+
+    pub fn get_bit_depth(&self) -> u16 {
+        self.mode.bit_depth()
+    }
+
+    pub fn get_refresh_rate(&self) -> u16 {
+        self.mode.refresh_rate()
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// Resolves `#include "file"`, injects `#define`s and the `#version` header
+/// in GLSL sources, keeping track of where each line comes from.
+///
+/// Includes are looked up next to the including file, then in the virtual
+/// files, then in the directories by order of addition. `#pragma once` skips
+/// files already included.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderPreprocessor {
+    files: HashMap<String, String>,
+    directories: Vec<PathBuf>,
+    defines: BTreeMap<String, String>,
+    header: Option<String>,
+}
+
+impl ShaderPreprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a virtual file, e.g. embedded with `include_str!`.
+    pub fn add_file<S>(&mut self, name: &str, source: S) -> &mut Self
+    where
+        S: ToString,
+    {
+        self.files.insert(normalize(name), source.to_string());
+        self
+    }
+
+    pub fn add_directory<P>(&mut self, directory: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.directories.push(directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// `#define name value`, `value` may be empty.
+    pub fn define<S>(&mut self, name: &str, value: S) -> &mut Self
+    where
+        S: ToString,
+    {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.defines.remove(name);
+        self
+    }
+
+    /// `#version` and precision lines put first, e.g. `GraphicsDevice::get_glsl_header`.
+    /// Ignored for sources starting with their own `#version`.
+    pub fn set_header(&mut self, header: Option<&str>) -> &mut Self {
+        self.header = header.map(|header| header.to_string());
+        self
+    }
+
+    pub fn get_header(&self) -> Option<&str> {
+        self.header.as_deref()
+    }
+
+    /// Processes file `name`.
+    pub fn process(&self, name: &str) -> Result<ProcessedShader> {
+        self.process_with(name, None, &[])
+    }
+
+    /// Processes file `name` with `header` instead of the one set, and
+    /// `defines` on top of the ones set.
+    pub fn process_with(
+        &self,
+        name: &str,
+        header: Option<&str>,
+        defines: &[(&str, &str)],
+    ) -> Result<ProcessedShader> {
+        let (name, source) = self.load(name, None)?;
+        self.process_source_with(&name, &source, header, defines)
+    }
+
+    /// Processes `source`, named `name` in the line map and for relative includes.
+    pub fn process_source(&self, name: &str, source: &str) -> Result<ProcessedShader> {
+        self.process_source_with(name, source, None, &[])
+    }
+
+    pub fn process_source_with(
+        &self,
+        name: &str,
+        source: &str,
+        header: Option<&str>,
+        defines: &[(&str, &str)],
+    ) -> Result<ProcessedShader> {
+        let mut output = ProcessedShader::default();
+        let mut lines: Vec<(usize, &str)> = source.lines().enumerate().collect();
+
+        // #version has to stay first, only comments and blank lines may precede it
+        let first = lines.iter().position(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with("//")
+        });
+
+        match first {
+            Some(first) if lines[first].1.trim().starts_with("#version") => {
+                let (index, line) = lines.remove(first);
+                output.push(line, name, index + 1);
+            }
+            _ => {
+                let header = header.or_else(|| self.get_header()).unwrap_or("");
+                for line in header.lines() {
+                    output.push(line, "<header>", 0);
+                }
+            }
+        }
+
+        let mut all_defines = self.defines.clone();
+        for (define, value) in defines {
+            all_defines.insert(define.to_string(), value.to_string());
+        }
+        for (define, value) in &all_defines {
+            output.push(format!("#define {} {}", define, value).trim_end(), "<defines>", 0);
+        }
+
+        let mut state = IncludeState::default();
+        state.stack.push(normalize(name));
+        self.expand(name, &lines, &mut output, &mut state)?;
+
+        Ok(output)
+    }
+
+    fn expand(
+        &self,
+        name: &str,
+        lines: &[(usize, &str)],
+        output: &mut ProcessedShader,
+        state: &mut IncludeState,
+    ) -> Result<()> {
+        for (index, line) in lines {
+            let trimmed = line.trim();
+
+            if trimmed == "#pragma once" {
+                state.once.insert(normalize(name));
+                continue;
+            }
+
+            if let Some(argument) = trimmed.strip_prefix("#include") {
+                let include = parse_include(argument).ok_or_else(|| {
+                    failure::err_msg(format!("{}:{}: malformed #include", name, index + 1))
+                })?;
+                let (include_name, source) = self.load(include, Some(name)).map_err(|err| {
+                    failure::err_msg(format!("{}:{}: {}", name, index + 1, err))
+                })?;
+
+                if state.once.contains(&include_name) {
+                    continue;
+                }
+                if state.stack.contains(&include_name) {
+                    return Err(failure::err_msg(format!(
+                        "{}:{}: recursive #include of {}",
+                        name,
+                        index + 1,
+                        include_name
+                    ))
+                    .into());
+                }
+
+                state.stack.push(include_name.clone());
+                let include_lines: Vec<(usize, &str)> = source.lines().enumerate().collect();
+                self.expand(&include_name, &include_lines, output, state)?;
+                state.stack.pop();
+                continue;
+            }
+
+            output.push(line, name, index + 1);
+        }
+        Ok(())
+    }
+
+    /// Finds `name`, relative to `from` first, returning its normalized name and source.
+    fn load(&self, name: &str, from: Option<&str>) -> Result<(String, String)> {
+        let mut candidates = Vec::new();
+        if let Some(parent) = from.and_then(|from| Path::new(from).parent()) {
+            if !parent.as_os_str().is_empty() {
+                candidates.push(normalize(&parent.join(name).to_string_lossy()));
+            }
+        }
+        candidates.push(normalize(name));
+
+        for candidate in &candidates {
+            if let Some(source) = self.files.get(candidate) {
+                return Ok((candidate.clone(), source.clone()));
+            }
+        }
+
+        for directory in &self.directories {
+            for candidate in &candidates {
+                let path = directory.join(candidate);
+                if path.is_file() {
+                    return Ok((candidate.clone(), fs::read_to_string(path)?));
+                }
+            }
+        }
+
+        Err(failure::err_msg(format!("Shader file {} not found", name)).into())
+    }
+}
+
+#[derive(Default)]
+struct IncludeState {
+    stack: Vec<String>,
+    once: HashSet<String>,
+}
+
+fn parse_include(argument: &str) -> Option<&str> {
+    let argument = argument.trim();
+    let (open, close) = match argument.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let argument = argument.strip_prefix(open)?;
+    let end = argument.find(close)?;
+    Some(&argument[..end])
+}
+
+/// Forward slashes and no `.` or `..` components, so a file has one name.
+fn normalize(name: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderSourceMap {
+    /// File and line of each output line, line 0 for generated lines.
+    origins: Vec<(String, usize)>,
+}
+
+/// Output of `ShaderPreprocessor`, ready for `GraphicsDevice::new_program`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessedShader {
+    pub source: String,
+    pub line_map: ShaderSourceMap,
+}
+
+impl ProcessedShader {
+    fn push(&mut self, line: &str, file: &str, line_number: usize) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.line_map.origins.push((file.to_string(), line_number));
+    }
+}
+
+impl ShaderSourceMap {
+    /// File and line of output line `line`, counting from 1.
+    pub fn get_origin(&self, line: usize) -> Option<(&str, usize)> {
+        self.origins
+            .get(line.checked_sub(1)?)
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    /// Rewrites the line references of a driver log, e.g. `0:12(5): error`
+    /// (Mesa), `0(12) : error` (NVIDIA) or `ERROR: 0:12:` (AMD), to `file:line`.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_log_line(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        match find_line_reference(line) {
+            Some((start, end, line_number)) => match self.get_origin(line_number) {
+                Some((file, origin)) => format!("{}{}:{}{}", &line[..start], file, origin, &line[end..]),
+                None => line.to_string(),
+            },
+            None => line.to_string(),
+        }
+    }
+}
+
+/// Finds `<source>:<line>` or `<source>(<line>)`, returning its range and line.
+fn find_line_reference(line: &str) -> Option<(usize, usize, usize)> {
+    let bytes = line.as_bytes();
+    let digits_end = |from: usize| {
+        let mut end = from;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+        end
+    };
+
+    let mut start = 0;
+    while start < bytes.len() {
+        let preceded_by_digit = start > 0 && bytes[start - 1].is_ascii_digit();
+        if bytes[start].is_ascii_digit() && !preceded_by_digit {
+            let source_end = digits_end(start);
+            if source_end + 1 < bytes.len() {
+                let separator = bytes[source_end];
+                let line_end = digits_end(source_end + 1);
+                if line_end > source_end + 1 {
+                    let line_number = line[source_end + 1..line_end].parse().ok()?;
+                    match separator {
+                        b':' => return Some((start, line_end, line_number)),
+                        b'(' if bytes.get(line_end) == Some(&b')') => {
+                            return Some((start, line_end + 1, line_number))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            start = source_end;
+        } else {
+            start += 1;
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::ShaderPreprocessor;
+
+    fn preprocessor() -> ShaderPreprocessor {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor
+            .add_file("common.glsl", "#pragma once\nfloat square(float x) { return x * x; }\n")
+            .add_file("lib/light.glsl", "#include \"../common.glsl\"\n#include \"util.glsl\"\nfloat light() { return square(LIGHT); }\n")
+            .add_file("lib/util.glsl", "float util;\n")
+            .add_file("main.frag", "// main\n#include \"common.glsl\"\n#include <lib/light.glsl>\nvoid main() {}\n")
+            .add_file("loop.glsl", "#include \"loop.glsl\"\n")
+            .define("LIGHT", 2.0)
+            .set_header(Some("#version 330 core\n"));
+        preprocessor
+    }
+
+    #[test]
+    fn resolves_includes_and_defines() {
+        let shader = preprocessor().process_with("main.frag", None, &[("FOG", "")]).unwrap();
+
+        assert_eq!(
+            shader.source,
+            "#version 330 core\n#define FOG\n#define LIGHT 2\n// main\nfloat square(float x) { return x * x; }\n\
+             float util;\nfloat light() { return square(LIGHT); }\nvoid main() {}\n"
+        );
+        assert_eq!(shader.line_map.get_origin(1), Some(("<header>", 0)));
+        assert_eq!(shader.line_map.get_origin(6), Some(("lib/util.glsl", 1)));
+        assert_eq!(shader.line_map.get_origin(8), Some(("main.frag", 4)));
+    }
+
+    #[test]
+    fn keeps_source_version_and_reports_errors() {
+        let preprocessor = preprocessor();
+
+        let shader = preprocessor.process_source("inline", "\n#version 100\nvoid main() {}").unwrap();
+        assert!(shader.source.starts_with("#version 100\n#define LIGHT 2\n"));
+        assert_eq!(shader.line_map.get_origin(1), Some(("inline", 2)));
+
+        assert!(preprocessor.process("loop.glsl").unwrap_err().to_string().contains("recursive"));
+        assert!(preprocessor.process_source("a", "#include \"missing.glsl\"").is_err());
+        assert!(preprocessor.process_source("a", "#include missing").is_err());
+    }
+
+    #[test]
+    fn maps_driver_logs() {
+        let shader = preprocessor().process("main.frag").unwrap();
+
+        assert_eq!(
+            shader.line_map.map_log("0:4(12): error: syntax error"),
+            "common.glsl:2(12): error: syntax error"
+        );
+        assert_eq!(shader.line_map.map_log("0(7) : error C0000: oops"), "main.frag:4 : error C0000: oops");
+        assert_eq!(shader.line_map.map_log("ERROR: 0:6: 'x' : undeclared"), "ERROR: lib/light.glsl:3: 'x' : undeclared");
+        assert_eq!(shader.line_map.map_log("no reference"), "no reference");
+    }
+
+}
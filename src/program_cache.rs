@@ -0,0 +1,231 @@
+use std::fs;
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::Result;
+
+type GetProgramBinary = unsafe extern "system" fn(u32, i32, *mut i32, *mut u32, *mut c_void);
+type ProgramBinary = unsafe extern "system" fn(u32, u32, *const c_void, i32);
+type ProgramParameteri = unsafe extern "system" fn(u32, u32, i32);
+type GetProgramiv = unsafe extern "system" fn(u32, u32, *mut i32);
+
+/// `GL_ARB_get_program_binary` entry points, which glow doesn't expose.
+#[derive(Clone, Copy)]
+pub(crate) struct ProgramBinaryFunctions {
+    get_program_binary: GetProgramBinary,
+    program_binary: ProgramBinary,
+    /// Missing from `GL_OES_get_program_binary`, which needs no hint.
+    program_parameteri: Option<ProgramParameteri>,
+    get_programiv: GetProgramiv,
+}
+
+impl ProgramBinaryFunctions {
+    /// None if the loader doesn't know one of the functions, also looking
+    /// for the `OES` names of GLES 2. `glProgramParameteri` is optional.
+    pub(crate) fn load<F>(mut loader: F) -> Option<ProgramBinaryFunctions>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        let program_parameteri = unsafe { cast_function(loader("glProgramParameteri")) };
+        let mut load = |name: &str| {
+            let pointer = loader(name);
+            if pointer.is_null() {
+                loader(&format!("{}OES", name))
+            } else {
+                pointer
+            }
+        };
+
+        unsafe {
+            Some(ProgramBinaryFunctions {
+                get_program_binary: cast_function(load("glGetProgramBinary"))?,
+                program_binary: cast_function(load("glProgramBinary"))?,
+                program_parameteri,
+                get_programiv: cast_function(load("glGetProgramiv"))?,
+            })
+        }
+    }
+
+    pub(crate) unsafe fn set_retrievable_hint(&self, program: u32) {
+        if let Some(program_parameteri) = self.program_parameteri {
+            program_parameteri(program, glow::PROGRAM_BINARY_RETRIEVABLE_HINT, 1);
+        }
+    }
+
+    /// Format and content of the binary of a linked program.
+    pub(crate) unsafe fn get_binary(&self, program: u32) -> Option<(u32, Vec<u8>)> {
+        let mut length = 0;
+        (self.get_programiv)(program, glow::PROGRAM_BINARY_LENGTH, &mut length);
+        if length <= 0 {
+            return None;
+        }
+
+        let mut data = vec![0u8; length as usize];
+        let mut written = 0;
+        let mut format = 0;
+        (self.get_program_binary)(program, length, &mut written, &mut format, data.as_mut_ptr() as *mut c_void);
+        data.truncate(written.max(0) as usize);
+        if data.is_empty() {
+            None
+        } else {
+            Some((format, data))
+        }
+    }
+
+    /// The program still has to be checked with `get_program_link_status`,
+    /// drivers reject binaries from other versions.
+    pub(crate) unsafe fn set_binary(&self, program: u32, format: u32, data: &[u8]) {
+        (self.program_binary)(program, format, data.as_ptr() as *const c_void, data.len() as i32);
+    }
+}
+
+/// Function pointer from a GL loader, None when the loader returned null.
+pub(crate) unsafe fn cast_function<T: Copy>(pointer: *const c_void) -> Option<T> {
+    if pointer.is_null() {
+        None
+    } else {
+        Some(std::mem::transmute_copy(&pointer))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgramCacheStats {
+    /// Programs loaded from a binary.
+    pub hits: usize,
+    /// Programs compiled, then stored.
+    pub misses: usize,
+    /// Binaries the driver refused, e.g. after an update, removed and compiled again.
+    pub rejected: usize,
+}
+
+/// Program binaries on disk for `GraphicsDevice::new_cached_program`, one
+/// file per program, keyed by the sources, attributes, renderer and version.
+#[derive(Debug)]
+pub struct ProgramCache {
+    directory: PathBuf,
+    stats: ProgramCacheStats,
+}
+
+impl ProgramCache {
+    /// Creates `directory` if needed.
+    pub fn new<P>(directory: P) -> Result<ProgramCache>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(ProgramCache {
+            directory: directory.as_ref().to_path_buf(),
+            stats: ProgramCacheStats::default(),
+        })
+    }
+
+    pub fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn get_stats(&self) -> ProgramCacheStats {
+        self.stats
+    }
+
+    /// Removes every cached binary.
+    pub fn clear(&mut self) -> Result<()> {
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "bin") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Key of a program, stable across runs and builds unlike `DefaultHasher`.
+    pub fn get_key(parts: &[&str]) -> u64 {
+        // FNV-1a, parts separated by a zero byte
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for part in parts {
+            for byte in part.bytes().chain(std::iter::once(0)) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    pub(crate) fn load(&self, key: u64) -> Option<(u32, Vec<u8>)> {
+        let data = fs::read(self.get_path(key)).ok()?;
+        if data.len() <= 4 {
+            return None;
+        }
+        let format = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        Some((format, data[4..].to_vec()))
+    }
+
+    pub(crate) fn store(&self, key: u64, format: u32, binary: &[u8]) -> Result<()> {
+        let mut data = Vec::with_capacity(binary.len() + 4);
+        data.extend_from_slice(&format.to_le_bytes());
+        data.extend_from_slice(binary);
+
+        // written aside then renamed, a crash never leaves half a binary
+        let path = self.get_path(key);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, key: u64) {
+        if let Err(err) = fs::remove_file(self.get_path(key)) {
+            warn!("Failed to remove program binary {:016x}: {}", key, err);
+        }
+    }
+
+    pub(crate) fn record_hit(&mut self) {
+        self.stats.hits += 1;
+    }
+
+    pub(crate) fn record_miss(&mut self) {
+        self.stats.misses += 1;
+    }
+
+    pub(crate) fn record_rejected(&mut self) {
+        self.stats.rejected += 1;
+    }
+
+    fn get_path(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.bin", key))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::ProgramCache;
+    use std::fs;
+
+    #[test]
+    fn stores_and_loads_binaries() {
+        let dir = std::env::temp_dir().join(format!("ckrl-program-cache-{}", std::process::id()));
+        let mut cache = ProgramCache::new(&dir).unwrap();
+
+        let key = ProgramCache::get_key(&["void main() {}", "void main() {}", "Mesa", "4.6"]);
+        assert_ne!(key, ProgramCache::get_key(&["void main() {}void main() {}", "", "Mesa", "4.6"]));
+        assert_eq!(ProgramCache::get_key(&[]), 0xcbf2_9ce4_8422_2325);
+        assert!(cache.load(key).is_none());
+
+        cache.store(key, 0x8e21, &[1, 2, 3]).unwrap();
+        assert_eq!(cache.load(key), Some((0x8e21, vec![1, 2, 3])));
+
+        cache.remove(key);
+        assert!(cache.load(key).is_none());
+
+        cache.store(key, 1, &[4]).unwrap();
+        cache.clear().unwrap();
+        assert!(cache.load(key).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,74 @@
+use crate::gl::ShaderStage;
+
+/// Shader validated at build time by `include_shader!` or `shader!`, with the
+/// declarations found in its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderInfo {
+    pub source: &'static str,
+    pub stage: ShaderStage,
+    /// Vertex shader inputs, empty for other stages.
+    pub attributes: &'static [ShaderAttribute],
+    /// Plain uniforms and samplers, uniform blocks having the type `block`.
+    pub uniforms: &'static [ShaderUniform],
+}
+
+impl ShaderInfo {
+    pub fn get_attribute(&self, name: &str) -> Option<&ShaderAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    pub fn get_uniform(&self, name: &str) -> Option<&ShaderUniform> {
+        self.uniforms.iter().find(|uniform| uniform.name == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderAttribute {
+    pub name: &'static str,
+    /// GLSL type, e.g. `vec3`.
+    pub ty: &'static str,
+    /// Set by `layout(location = n)`, otherwise bound before linking, see
+    /// `GraphicsDevice::new_program_with_attributes`.
+    pub location: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderUniform {
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+
+#[cfg(all(test, feature = "macros"))]
+mod tests {
+
+    use super::{ShaderAttribute, ShaderInfo};
+    use crate::gl::ShaderStage;
+
+    const VERTEX: ShaderInfo = crate::shader!(
+        vertex,
+        "#version 330 core
+        layout (location = 0) in vec3 aPos;
+        in vec4 aColor;
+        uniform mat4 Transform;
+        out vec4 Color;
+        void main()
+        {
+            Color = aColor;
+            gl_Position = Transform * vec4(aPos, 1.0);
+        }"
+    );
+
+    #[test]
+    fn macro_reflects_declarations() {
+        assert_eq!(VERTEX.stage, ShaderStage::Vertex);
+        assert_eq!(
+            VERTEX.get_attribute("aPos"),
+            Some(&ShaderAttribute { name: "aPos", ty: "vec3", location: Some(0) })
+        );
+        assert_eq!(VERTEX.get_attribute("aColor").unwrap().location, None);
+        assert_eq!(VERTEX.get_uniform("Transform").unwrap().ty, "mat4");
+        assert!(VERTEX.get_uniform("Color").is_none());
+    }
+
+}
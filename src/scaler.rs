@@ -0,0 +1,192 @@
+use crate::gl::{FilterMode, GraphicsDevice, RawRenderTarget};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalingMode {
+    /// Fills the window, ignoring the aspect ratio.
+    Stretch,
+    /// Largest size keeping the aspect ratio, with bars on the sides.
+    Letterbox,
+    /// Largest integer multiple of the virtual size, with bars on the sides.
+    PixelPerfect,
+    /// Fills the window keeping the aspect ratio, cutting what overflows.
+    Crop,
+}
+
+/// Renders at a fixed virtual resolution and scales the result to the window,
+/// everything drawn between `begin` and `end` lands on the virtual screen.
+pub struct ScreenScaler {
+    target: RawRenderTarget,
+    mode: ScalingMode,
+    filter: FilterMode,
+    bar_color: (f32, f32, f32, f32),
+    window_viewport: (i32, i32, i32, i32),
+    destination: (i32, i32, i32, i32),
+}
+
+impl ScreenScaler {
+    pub fn new(
+        device: &mut GraphicsDevice,
+        width: i32,
+        height: i32,
+        mode: ScalingMode,
+    ) -> Result<ScreenScaler> {
+        let target = device.new_render_target(width, height, FilterMode::Nearest)?;
+        let window_viewport = device.get_viewport();
+
+        Ok(ScreenScaler {
+            target,
+            mode,
+            filter: FilterMode::Nearest,
+            bar_color: (0.0, 0.0, 0.0, 1.0),
+            window_viewport,
+            destination: compute_destination(mode, (width, height), (window_viewport.2, window_viewport.3)),
+        })
+    }
+
+    pub fn set_mode(&mut self, mode: ScalingMode) {
+        self.mode = mode;
+        self.update_destination();
+    }
+
+    pub fn get_mode(&self) -> ScalingMode {
+        self.mode
+    }
+
+    /// Filter used when scaling, `Nearest` by default to keep pixels sharp.
+    pub fn set_filter(&mut self, filter: FilterMode) {
+        self.filter = filter;
+    }
+
+    pub fn set_bar_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.bar_color = (r, g, b, a);
+    }
+
+    pub fn get_virtual_size(&self) -> (i32, i32) {
+        (self.target.get_width(), self.target.get_height())
+    }
+
+    /// Area of the window covered by the virtual screen, in physical pixels
+    /// from the bottom-left corner.
+    pub fn get_destination(&self) -> (i32, i32, i32, i32) {
+        self.destination
+    }
+
+    pub fn get_render_target(&self) -> &RawRenderTarget {
+        &self.target
+    }
+
+    /// Redirects drawing to the virtual screen.
+    pub fn begin(&mut self, device: &mut GraphicsDevice) {
+        self.window_viewport = device.get_viewport();
+        self.update_destination();
+
+        device.bind_render_target(Some(&self.target));
+        device.set_viewport(0, 0, self.target.get_width(), self.target.get_height());
+    }
+
+    /// Draws the virtual screen to the window and restores the window viewport,
+    /// fails below GL 3.0 and GLES 3.0.
+    pub fn end(&mut self, device: &mut GraphicsDevice) -> Result<()> {
+        let (x, y, width, height) = self.window_viewport;
+        let (r, g, b, a) = self.bar_color;
+
+        device.bind_render_target(None);
+        device.set_viewport(x, y, width, height);
+        device.clear(r, g, b, a);
+        device.blit_render_target(&self.target, self.destination, self.filter)
+    }
+
+    /// Converts a position in physical window pixels, origin at the top-left
+    /// corner like `Input::get_mouse_position`, to virtual pixels.
+    pub fn to_virtual(&self, x: f64, y: f64) -> (f64, f64) {
+        let (dst_x, dst_y, dst_width, dst_height) = self.destination;
+        let window_height = self.window_viewport.3;
+        let (width, height) = self.get_virtual_size();
+
+        let top = (window_height - dst_y - dst_height) as f64;
+        let virtual_x = (x - dst_x as f64) * width as f64 / dst_width as f64;
+        let virtual_y = (y - top) * height as f64 / dst_height as f64;
+
+        (virtual_x, virtual_y)
+    }
+
+    /// Whether a virtual position returned by `to_virtual` lands on the virtual screen.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let (width, height) = self.get_virtual_size();
+        x >= 0.0 && y >= 0.0 && x < width as f64 && y < height as f64
+    }
+
+    fn update_destination(&mut self) {
+        self.destination = compute_destination(
+            self.mode,
+            self.get_virtual_size(),
+            (self.window_viewport.2, self.window_viewport.3),
+        );
+    }
+}
+
+fn compute_destination(
+    mode: ScalingMode,
+    (width, height): (i32, i32),
+    (window_width, window_height): (i32, i32),
+) -> (i32, i32, i32, i32) {
+    if width <= 0 || height <= 0 {
+        return (0, 0, window_width, window_height);
+    }
+
+    let scale_x = window_width as f64 / width as f64;
+    let scale_y = window_height as f64 / height as f64;
+
+    let (dst_width, dst_height) = match mode {
+        ScalingMode::Stretch => return (0, 0, window_width, window_height),
+        ScalingMode::Letterbox => {
+            let scale = scale_x.min(scale_y);
+            ((width as f64 * scale).round() as i32, (height as f64 * scale).round() as i32)
+        }
+        ScalingMode::PixelPerfect => {
+            let scale = (scale_x.min(scale_y).floor() as i32).max(1);
+            (width * scale, height * scale)
+        }
+        ScalingMode::Crop => {
+            let scale = scale_x.max(scale_y);
+            ((width as f64 * scale).round() as i32, (height as f64 * scale).round() as i32)
+        }
+    };
+
+    (
+        (window_width - dst_width) / 2,
+        (window_height - dst_height) / 2,
+        dst_width,
+        dst_height,
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{compute_destination, ScalingMode};
+
+    #[test]
+    fn letterbox_adds_bars_on_the_sides() {
+        let destination = compute_destination(ScalingMode::Letterbox, (320, 180), (1000, 450));
+        assert_eq!(destination, (100, 0, 800, 450));
+    }
+
+    #[test]
+    fn pixel_perfect_uses_integer_scale() {
+        let destination = compute_destination(ScalingMode::PixelPerfect, (320, 180), (1000, 600));
+        assert_eq!(destination, (20, 30, 960, 540));
+
+        let destination = compute_destination(ScalingMode::PixelPerfect, (320, 180), (200, 100));
+        assert_eq!(destination, (-60, -40, 320, 180));
+    }
+
+    #[test]
+    fn crop_overflows_the_window() {
+        let destination = compute_destination(ScalingMode::Crop, (320, 180), (640, 640));
+        assert_eq!(destination, (-249, 0, 1138, 640));
+    }
+
+}
@@ -1,17 +1,323 @@
-pub static VERTEX_SHADER: &str = r#"#version 330 core
-layout (location = 0) in vec3 aPos;
+use crate::gl::{GlApi, GlVersion};
+
+pub const ATTRIBUTE_POSITION: u32 = 0;
+pub const ATTRIBUTE_TEX_COORD: u32 = 1;
+pub const ATTRIBUTE_COLOR: u32 = 2;
+
+/// Names bound to the attribute locations before linking.
+pub static ATTRIBUTES: &[(u32, &str)] = &[
+    (ATTRIBUTE_POSITION, "aPos"),
+    (ATTRIBUTE_TEX_COORD, "aTexCoord"),
+    (ATTRIBUTE_COLOR, "aColor"),
+];
+
+/// Built-in programs, written once and wrapped for each `ShaderDialect`.
+///
+/// They share the `ATTRIBUTES` locations. Every vertex shader but `Blit`
+/// applies a `mat4 Transform` uniform to the position, e.g. `Context::get_projection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinShader {
+    /// `vec4 Color` uniform.
+    FlatColor,
+    /// Per-vertex `aColor`.
+    VertexColor,
+    /// `sampler2D Texture` uniform, sampled at `aTexCoord`.
+    Textured,
+    /// `Textured` multiplied by the per-vertex `aColor`.
+    TexturedTint,
+    /// Signed distance field glyphs in the alpha channel of `Texture`, edge at
+    /// 0.5, `float Smoothing` uniform for the edge width, colored by `aColor`.
+    SdfText,
+    /// Copies `Texture` with positions already in clip space, e.g. a quad
+    /// from -1 to 1.
+    Blit,
+}
+
+impl BuiltinShader {
+    pub const ALL: [BuiltinShader; 6] = [
+        BuiltinShader::FlatColor,
+        BuiltinShader::VertexColor,
+        BuiltinShader::Textured,
+        BuiltinShader::TexturedTint,
+        BuiltinShader::SdfText,
+        BuiltinShader::Blit,
+    ];
+
+    /// Complete vertex and fragment sources for `dialect`.
+    pub fn get_sources(self, dialect: ShaderDialect) -> (String, String) {
+        let (vertex, fragment) = self.get_bodies();
+        (
+            format!("{}{}", dialect.vertex_prelude(), vertex),
+            format!("{}{}", dialect.fragment_prelude(), fragment),
+        )
+    }
+
+    fn get_bodies(self) -> (&'static str, &'static str) {
+        match self {
+            BuiltinShader::FlatColor => (POSITION_VERTEX, FLAT_COLOR_FRAGMENT),
+            BuiltinShader::VertexColor => (COLOR_VERTEX, VERTEX_COLOR_FRAGMENT),
+            BuiltinShader::Textured => (TEX_COORD_VERTEX, TEXTURED_FRAGMENT),
+            BuiltinShader::TexturedTint => (TEX_COORD_COLOR_VERTEX, TEXTURED_TINT_FRAGMENT),
+            BuiltinShader::SdfText => (TEX_COORD_COLOR_VERTEX, SDF_TEXT_FRAGMENT),
+            BuiltinShader::Blit => (BLIT_VERTEX, TEXTURED_FRAGMENT),
+        }
+    }
+}
+
+/// GLSL flavours the built-in programs are written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderDialect {
+    Glsl330,
+    GlslEs300,
+    /// GL 3.1 and 3.2, whose core profiles reject `Glsl120`.
+    Glsl140,
+    Glsl120,
+    GlslEs100,
+}
+
+impl ShaderDialect {
+    /// Picks the dialect from `GL_SHADING_LANGUAGE_VERSION`, e.g. `4.60 NVIDIA`
+    /// or `OpenGL ES GLSL ES 3.00`.
+    pub fn from_shading_language_version(version: &str) -> Option<ShaderDialect> {
+        let es = version.contains(" ES");
+        let number = version
+            .split_whitespace()
+            .find_map(|word| {
+                let mut parts = word.splitn(2, '.');
+                let major: u32 = parts.next()?.parse().ok()?;
+                let minor: String = parts.next()?.chars().take_while(|c| c.is_ascii_digit()).collect();
+                let minor: u32 = format!("{:0<2}", minor).get(..2)?.parse().ok()?;
+                Some(major * 100 + minor)
+            })?;
+
+        Some(match (es, number) {
+            (true, number) if number >= 300 => ShaderDialect::GlslEs300,
+            (true, _) => ShaderDialect::GlslEs100,
+            (false, number) if number >= 330 => ShaderDialect::Glsl330,
+            (false, number) if number >= 140 => ShaderDialect::Glsl140,
+            (false, _) => ShaderDialect::Glsl120,
+        })
+    }
+
+    /// Fallback when the shading language version can't be parsed.
+    pub fn from_gl_version(version: GlVersion) -> ShaderDialect {
+        match (version.api, version.major, version.minor) {
+            (GlApi::OpenGlEs, major, _) if major >= 3 => ShaderDialect::GlslEs300,
+            (GlApi::OpenGlEs, _, _) => ShaderDialect::GlslEs100,
+            (GlApi::OpenGl, major, minor) if (major, minor) >= (3, 3) => ShaderDialect::Glsl330,
+            (GlApi::OpenGl, major, minor) if (major, minor) >= (3, 1) => ShaderDialect::Glsl140,
+            (GlApi::OpenGl, _, _) => ShaderDialect::Glsl120,
+        }
+    }
+
+    pub fn is_es(self) -> bool {
+        matches!(self, ShaderDialect::GlslEs300 | ShaderDialect::GlslEs100)
+    }
+
+    pub fn get_version_line(self) -> &'static str {
+        match self {
+            ShaderDialect::Glsl330 => "#version 330 core\n",
+            ShaderDialect::GlslEs300 => "#version 300 es\n",
+            ShaderDialect::Glsl140 => "#version 140\n",
+            ShaderDialect::Glsl120 => "#version 120\n",
+            ShaderDialect::GlslEs100 => "#version 100\n",
+        }
+    }
+
+    /// Version line with a default float precision on GLES, for sources
+    /// written without one, e.g. `GraphicsDevice::get_glsl_header`.
+    pub fn get_header(self) -> String {
+        let precision = if self.is_es() { "precision mediump float;\n" } else { "" };
+        format!("{}{}", self.get_version_line(), precision)
+    }
+
+    fn is_legacy(self) -> bool {
+        matches!(self, ShaderDialect::Glsl120 | ShaderDialect::GlslEs100)
+    }
+
+    /// Version line plus `ATTRIBUTE` and `VARYING` keywords.
+    pub(crate) fn vertex_prelude(self) -> String {
+        let keywords = if self.is_legacy() {
+            "#define ATTRIBUTE attribute\n#define VARYING varying\n"
+        } else {
+            "#define ATTRIBUTE in\n#define VARYING out\n"
+        };
+        format!("{}{}", self.get_version_line(), keywords)
+    }
+
+    /// Version line, float precision on GLES, `VARYING`, `texture` and `FragColor`.
+    pub(crate) fn fragment_prelude(self) -> String {
+        let keywords = if self.is_legacy() {
+            "#define VARYING varying\n#define texture texture2D\n#define FragColor gl_FragColor\n"
+        } else {
+            "#define VARYING in\nout vec4 FragColor;\n"
+        };
+        format!("{}{}", self.get_header(), keywords)
+    }
+}
+
+
+static POSITION_VERTEX: &str = r#"ATTRIBUTE vec3 aPos;
+
+uniform mat4 Transform;
 
 void main()
 {
-    gl_Position = vec4(aPos.x, aPos.y, aPos.z, 1.0);
+    gl_Position = Transform * vec4(aPos, 1.0);
 }
 "#;
 
-pub static FRAGMENT_SHADER: &str = r#"#version 330 core
-out vec4 FragColor;
+static COLOR_VERTEX: &str = r#"ATTRIBUTE vec3 aPos;
+ATTRIBUTE vec4 aColor;
+
+uniform mat4 Transform;
+
+VARYING vec4 Color;
+
+void main()
+{
+    Color = aColor;
+    gl_Position = Transform * vec4(aPos, 1.0);
+}
+"#;
+
+static TEX_COORD_VERTEX: &str = r#"ATTRIBUTE vec3 aPos;
+ATTRIBUTE vec2 aTexCoord;
+
+uniform mat4 Transform;
+
+VARYING vec2 TexCoord;
 
 void main()
 {
-    FragColor = vec4(1.0, 0.5, 0.2, 1.0);
+    TexCoord = aTexCoord;
+    gl_Position = Transform * vec4(aPos, 1.0);
+}
+"#;
+
+static TEX_COORD_COLOR_VERTEX: &str = r#"ATTRIBUTE vec3 aPos;
+ATTRIBUTE vec2 aTexCoord;
+ATTRIBUTE vec4 aColor;
+
+uniform mat4 Transform;
+
+VARYING vec2 TexCoord;
+VARYING vec4 Color;
+
+void main()
+{
+    TexCoord = aTexCoord;
+    Color = aColor;
+    gl_Position = Transform * vec4(aPos, 1.0);
+}
+"#;
+
+static BLIT_VERTEX: &str = r#"ATTRIBUTE vec2 aPos;
+ATTRIBUTE vec2 aTexCoord;
+
+VARYING vec2 TexCoord;
+
+void main()
+{
+    TexCoord = aTexCoord;
+    gl_Position = vec4(aPos, 0.0, 1.0);
+}
+"#;
+
+static FLAT_COLOR_FRAGMENT: &str = r#"uniform vec4 Color;
+
+void main()
+{
+    FragColor = Color;
+}
+"#;
+
+static VERTEX_COLOR_FRAGMENT: &str = r#"VARYING vec4 Color;
+
+void main()
+{
+    FragColor = Color;
+}
+"#;
+
+static TEXTURED_FRAGMENT: &str = r#"VARYING vec2 TexCoord;
+
+uniform sampler2D Texture;
+
+void main()
+{
+    FragColor = texture(Texture, TexCoord);
+}
+"#;
+
+static TEXTURED_TINT_FRAGMENT: &str = r#"VARYING vec2 TexCoord;
+VARYING vec4 Color;
+
+uniform sampler2D Texture;
+
+void main()
+{
+    FragColor = Color * texture(Texture, TexCoord);
+}
+"#;
+
+static SDF_TEXT_FRAGMENT: &str = r#"VARYING vec2 TexCoord;
+VARYING vec4 Color;
+
+uniform sampler2D Texture;
+uniform float Smoothing;
+
+void main()
+{
+    float distance = texture(Texture, TexCoord).a;
+    float alpha = smoothstep(0.5 - Smoothing, 0.5 + Smoothing, distance);
+    FragColor = vec4(Color.rgb, Color.a * alpha);
+}
+"#;
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{BuiltinShader, ShaderDialect};
+    use crate::gl::GlVersion;
+
+    #[test]
+    fn parses_shading_language_versions() {
+        let parse = ShaderDialect::from_shading_language_version;
+
+        assert_eq!(parse("4.60 NVIDIA"), Some(ShaderDialect::Glsl330));
+        assert_eq!(parse("3.30"), Some(ShaderDialect::Glsl330));
+        assert_eq!(parse("1.50 - Build 20.19"), Some(ShaderDialect::Glsl140));
+        assert_eq!(parse("1.30"), Some(ShaderDialect::Glsl120));
+        assert_eq!(parse("1.2"), Some(ShaderDialect::Glsl120));
+        assert_eq!(parse("OpenGL ES GLSL ES 3.20"), Some(ShaderDialect::GlslEs300));
+        assert_eq!(parse("OpenGL ES GLSL ES 1.0.17"), Some(ShaderDialect::GlslEs100));
+        assert_eq!(parse("unknown"), None);
+
+        assert_eq!(ShaderDialect::from_gl_version(GlVersion::gles(2, 0)), ShaderDialect::GlslEs100);
+        assert_eq!(ShaderDialect::from_gl_version(GlVersion::gl(3, 2)), ShaderDialect::Glsl140);
+    }
+
+    #[test]
+    fn header_follows_dialect() {
+        assert_eq!(ShaderDialect::Glsl330.get_header(), "#version 330 core\n");
+        assert_eq!(ShaderDialect::Glsl140.get_header(), "#version 140\n");
+        assert_eq!(ShaderDialect::GlslEs300.get_header(), "#version 300 es\nprecision mediump float;\n");
+        assert_eq!(ShaderDialect::GlslEs100.get_header(), "#version 100\nprecision mediump float;\n");
+    }
+
+    #[test]
+    fn sources_match_dialect() {
+        let (vertex, fragment) = BuiltinShader::TexturedTint.get_sources(ShaderDialect::GlslEs100);
+        assert!(vertex.starts_with("#version 100\n#define ATTRIBUTE attribute"));
+        assert!(fragment.contains("precision mediump float;\n#define VARYING varying"));
+        assert!(fragment.contains("#define FragColor gl_FragColor"));
+
+        let (vertex, fragment) = BuiltinShader::Blit.get_sources(ShaderDialect::Glsl140);
+        assert!(vertex.starts_with("#version 140\n#define ATTRIBUTE in"));
+        assert!(fragment.contains("out vec4 FragColor;"));
+        assert!(!fragment.contains("precision"));
+    }
+
 }
-"#;
\ No newline at end of file
@@ -0,0 +1,127 @@
+/// Frames a persistently mapped `StreamBuffer` is split into, so the CPU
+/// writes one while the GPU still reads the others.
+pub const STREAM_FRAMES: usize = 3;
+
+/// Allocations are aligned to this many bytes, enough for any vertex attribute.
+pub const STREAM_ALIGNMENT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Vertex,
+    Index,
+}
+
+impl From<StreamKind> for u32 {
+    fn from(kind: StreamKind) -> u32 {
+        match kind {
+            StreamKind::Vertex => glow::ARRAY_BUFFER,
+            StreamKind::Index => glow::ELEMENT_ARRAY_BUFFER,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamMode {
+    /// One frame of storage, replaced with `glBufferData` at each frame so
+    /// the driver hands out fresh memory instead of waiting for the GPU.
+    Orphaning,
+    /// `glBufferStorage` mapped once for `STREAM_FRAMES` frames, each frame
+    /// waiting on the fence of the one written `STREAM_FRAMES` frames ago.
+    Persistent,
+}
+
+/// Bytes written in a `StreamBuffer` for the current frame, offset from the
+/// start of the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSlice {
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Ring of frame regions, each handing out aligned slices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamAllocator {
+    frame_size: usize,
+    frames: usize,
+    frame: usize,
+    cursor: usize,
+}
+
+impl StreamAllocator {
+    pub fn new(frame_size: usize, frames: usize) -> Self {
+        Self {
+            frame_size,
+            frames: frames.max(1),
+            frame: 0,
+            cursor: 0,
+        }
+    }
+
+    /// Moves to the next frame region, returning its index.
+    pub fn begin_frame(&mut self) -> usize {
+        self.frame = (self.frame + 1) % self.frames;
+        self.cursor = 0;
+        self.frame
+    }
+
+    /// Slice of `size` bytes in the current region, None once it is full.
+    pub fn allocate(&mut self, size: usize) -> Option<StreamSlice> {
+        let start = self.cursor.div_ceil(STREAM_ALIGNMENT) * STREAM_ALIGNMENT;
+        if start + size > self.frame_size {
+            return None;
+        }
+
+        self.cursor = start + size;
+        Some(StreamSlice {
+            offset: self.frame * self.frame_size + start,
+            size,
+        })
+    }
+
+    pub fn get_frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn get_frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Bytes used in the current frame, alignment included.
+    pub fn get_used(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn get_total_size(&self) -> usize {
+        self.frame_size * self.frames
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{StreamAllocator, StreamSlice};
+
+    #[test]
+    fn allocates_aligned_slices_per_frame() {
+        let mut allocator = StreamAllocator::new(64, 3);
+        assert_eq!(allocator.get_total_size(), 192);
+
+        assert_eq!(allocator.allocate(10), Some(StreamSlice { offset: 0, size: 10 }));
+        assert_eq!(allocator.allocate(20), Some(StreamSlice { offset: 16, size: 20 }));
+        assert_eq!(allocator.get_used(), 36);
+        assert_eq!(allocator.allocate(20), None);
+        assert_eq!(allocator.allocate(16), Some(StreamSlice { offset: 48, size: 16 }));
+
+        assert_eq!(allocator.begin_frame(), 1);
+        assert_eq!(allocator.allocate(8), Some(StreamSlice { offset: 64, size: 8 }));
+        allocator.begin_frame();
+        assert_eq!(allocator.begin_frame(), 0);
+        assert_eq!(allocator.allocate(64), Some(StreamSlice { offset: 0, size: 64 }));
+
+        let mut single = StreamAllocator::new(32, 1);
+        assert_eq!(single.begin_frame(), 0);
+        assert_eq!(single.allocate(40), None);
+    }
+
+}
@@ -0,0 +1,197 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use log::{debug, info};
+
+use crate::gl::{GraphicsDevice, RawProgram};
+use crate::preprocessor::{ProcessedShader, ShaderPreprocessor};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VariantStats {
+    /// Programs compiled and cached.
+    pub variants: usize,
+    pub requests: usize,
+    /// Requests served without preprocessing.
+    pub hits: usize,
+    /// Keyword sets whose sources matched an existing variant.
+    pub shared: usize,
+    pub failures: usize,
+    /// Requests for keyword sets that failed before.
+    pub repeated_failures: usize,
+}
+
+/// Variants of a vertex and fragment pair, one per set of feature keywords,
+/// e.g. `NORMAL_MAP` or `FOG`, each keyword becoming a `#define`.
+///
+/// A variant is compiled the first time it is requested, then cached by the
+/// hash of its preprocessed sources. Keywords the sources never mention get
+/// no `#define`, so keyword sets differing only by those share a program.
+pub struct ShaderVariants {
+    preprocessor: ShaderPreprocessor,
+    vertex: String,
+    fragment: String,
+    programs: HashMap<u64, RawProgram>,
+    /// Keyword sets already requested, sorted.
+    keys: HashMap<Vec<String>, u64>,
+    /// Keyword sets that failed, with their error.
+    failed: HashMap<Vec<String>, String>,
+    stats: VariantStats,
+}
+
+impl ShaderVariants {
+    /// `vertex` and `fragment` are files of `preprocessor`.
+    pub fn new(preprocessor: ShaderPreprocessor, vertex: &str, fragment: &str) -> Self {
+        Self {
+            preprocessor,
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+            programs: HashMap::new(),
+            keys: HashMap::new(),
+            failed: HashMap::new(),
+            stats: VariantStats::default(),
+        }
+    }
+
+    /// Program with `keywords` defined, in any order, compiled if needed.
+    /// Keyword sets that failed return the same error, as a message, until
+    /// `clear`.
+    pub fn get(&mut self, device: &mut GraphicsDevice, keywords: &[&str]) -> Result<&RawProgram> {
+        self.stats.requests += 1;
+        let keywords = normalize_keywords(keywords);
+
+        if let Some(hash) = self.keys.get(&keywords) {
+            self.stats.hits += 1;
+            return Ok(&self.programs[hash]);
+        }
+        if let Some(err) = self.failed.get(&keywords) {
+            self.stats.repeated_failures += 1;
+            return Err(failure::err_msg(err.clone()).into());
+        }
+
+        let header = self.preprocessor.get_header().map(String::from).unwrap_or_else(|| device.get_glsl_header());
+        let processed = self.process(&header, &keywords).and_then(|(vertex, fragment)| {
+            let used: Vec<String> = keywords
+                .iter()
+                .filter(|keyword| mentions(&vertex, keyword) || mentions(&fragment, keyword))
+                .cloned()
+                .collect();
+            if used.len() == keywords.len() {
+                Ok((vertex, fragment))
+            } else {
+                self.process(&header, &used)
+            }
+        });
+        let (vertex, fragment) = match processed {
+            Ok(processed) => processed,
+            Err(err) => {
+                self.stats.failures += 1;
+                self.failed.insert(keywords, err.to_string());
+                return Err(err);
+            }
+        };
+
+        let hash = source_hash(&vertex.source, &fragment.source);
+        if self.programs.contains_key(&hash) {
+            debug!("Shader variant {:?} shares program {:x}", keywords, hash);
+            self.stats.shared += 1;
+        } else {
+            info!("Compiling shader variant {:?} of {}/{}", keywords, self.vertex, self.fragment);
+            match device.new_processed_program(&vertex, &fragment) {
+                Ok(program) => {
+                    self.programs.insert(hash, program);
+                    self.stats.variants += 1;
+                }
+                Err(err) => {
+                    self.stats.failures += 1;
+                    self.failed.insert(keywords, err.to_string());
+                    return Err(err);
+                }
+            }
+        }
+
+        self.keys.insert(keywords, hash);
+        Ok(&self.programs[&hash])
+    }
+
+    fn process(&self, header: &str, keywords: &[String]) -> Result<(ProcessedShader, ProcessedShader)> {
+        let defines: Vec<(&str, &str)> = keywords.iter().map(|keyword| (keyword.as_str(), "")).collect();
+        let vertex = self.preprocessor.process_with(&self.vertex, Some(header), &defines)?;
+        let fragment = self.preprocessor.process_with(&self.fragment, Some(header), &defines)?;
+        Ok((vertex, fragment))
+    }
+
+    pub fn get_stats(&self) -> VariantStats {
+        self.stats
+    }
+
+    pub fn get_preprocessor(&self) -> &ShaderPreprocessor {
+        &self.preprocessor
+    }
+
+    /// Deletes the compiled variants and forgets the failed ones, e.g. after
+    /// the sources changed. The next requests compile them again.
+    pub fn clear(&mut self) {
+        self.programs.clear();
+        self.keys.clear();
+        self.failed.clear();
+        self.stats.variants = 0;
+    }
+}
+
+/// Sorted without duplicates, so the order keywords are given in doesn't matter.
+fn normalize_keywords(keywords: &[&str]) -> Vec<String> {
+    let mut keywords: Vec<String> = keywords.iter().map(|keyword| keyword.to_string()).collect();
+    keywords.sort();
+    keywords.dedup();
+    keywords
+}
+
+/// Whether `keyword` appears in `shader` out of the injected `#define`s.
+fn mentions(shader: &ProcessedShader, keyword: &str) -> bool {
+    shader.source.lines().enumerate().any(|(index, line)| {
+        let injected = shader.line_map.get_origin(index + 1).is_some_and(|(file, _)| file == "<defines>");
+        !injected && line.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').any(|word| word == keyword)
+    })
+}
+
+fn source_hash(vertex: &str, fragment: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    vertex.hash(&mut hasher);
+    fragment.hash(&mut hasher);
+    hasher.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{mentions, normalize_keywords, source_hash};
+    use crate::preprocessor::ShaderPreprocessor;
+
+    #[test]
+    fn keyword_order_is_ignored() {
+        assert_eq!(normalize_keywords(&["FOG", "NORMAL_MAP", "FOG"]), normalize_keywords(&["NORMAL_MAP", "FOG"]));
+    }
+
+    #[test]
+    fn only_mentioned_keywords_count() {
+        let source = "#version 330 core\n#ifdef FOG\nfloat fog;\n#endif\nfloat FOGGY;\n";
+        let shader = ShaderPreprocessor::new()
+            .process_source_with("shader.glsl", source, None, &[("FOG", ""), ("SKINNING", ""), ("FOGGY_X", "")])
+            .unwrap();
+        assert!(mentions(&shader, "FOG"));
+        assert!(mentions(&shader, "FOGGY"));
+        assert!(!mentions(&shader, "SKINNING"));
+        assert!(!mentions(&shader, "FOGGY_X"));
+    }
+
+    #[test]
+    fn hash_covers_both_stages() {
+        assert_eq!(source_hash("a", "b"), source_hash("a", "b"));
+        assert_ne!(source_hash("a", "b"), source_hash("b", "a"));
+        assert_ne!(source_hash("ab", ""), source_hash("a", "b"));
+    }
+
+}
@@ -1,60 +1,348 @@
-use glutin::{dpi::LogicalSize, event_loop::EventLoop, window::WindowBuilder, ContextBuilder, PixelFormat};
+use std::path::Path;
+
+use glutin::{dpi::{LogicalPosition, LogicalSize}, event_loop::EventLoop, window::WindowBuilder, ContextBuilder, PixelFormat};
+use glutin::event_loop::EventLoopWindowTarget;
+use glutin::window::{CursorIcon, Fullscreen, Icon, WindowId};
+use glutin::{Api, GlRequest};
 use glow::Context as GlowContext;
+use log::{info, warn};
 use crate::Result;
 use crate::context;
+use crate::gl::{GlApi, GlVersion};
+use crate::monitor::{FullscreenMode, Monitor};
 
 pub struct Window {
-    pub el: glutin::event_loop::EventLoop<()>,
-    pub wc: glutin::WindowedContext<glutin::PossiblyCurrent>,
+    /// Only empty while `make_current` swaps it.
+    wc: Option<glutin::WindowedContext<glutin::PossiblyCurrent>>,
+    /// Binding cache of this window's GL context in `GraphicsDevice`.
+    pub(crate) context_id: usize,
 }
 
 impl Window {
-    pub fn new(settings: &context::ContextBuilder) -> Result<(Window, GlowContext)> {
-        let el = EventLoop::new();
+    pub fn new<E>(
+        settings: &context::ContextBuilder,
+        el: &EventLoop<E>,
+    ) -> Result<(Window, GlowContext)> {
+        let fullscreen = match settings.platform_hints.fullscreen {
+            FullscreenMode::Windowed => None,
+            mode => select_monitor(el, settings.platform_hints.monitor)?.to_fullscreen(mode)?,
+        };
+
         let wb = WindowBuilder::new()
             .with_title(settings.window_title.clone())
             .with_inner_size(LogicalSize::new(
                 settings.window_width,
                 settings.window_height
-            ));
-    
-        let windowed_context = ContextBuilder::new()
-            .with_gl(settings.platform_hints.gl_version)
-            .with_gl_profile(settings.platform_hints.gl_profile)
-            .with_hardware_acceleration(Some(settings.platform_hints.hardware_acceleration))
-            .with_vsync(settings.platform_hints.vsync)
-            .with_srgb(settings.platform_hints.srgb)
-            .build_windowed(wb, &el)
-            .unwrap();
+            ))
+            .with_fullscreen(fullscreen);
     
-        let windowed_context = unsafe { windowed_context.make_current().unwrap() };
+        let windowed_context = if settings.gl_versions.is_empty() {
+            ContextBuilder::new()
+                .with_gl(settings.platform_hints.gl_version)
+                .with_gl_profile(settings.platform_hints.gl_profile)
+                .with_hardware_acceleration(Some(settings.platform_hints.hardware_acceleration))
+                .with_vsync(settings.platform_hints.vsync)
+                .with_srgb(settings.platform_hints.srgb)
+                .build_windowed(wb, el)?
+        } else {
+            build_versioned_context(settings, wb, el)?
+        };
     
-        if settings.platform_hints.fullscreen {
-            let mh = el.available_monitors().nth(0).unwrap();
-            windowed_context
-            .window()
-            .set_fullscreen(Some(glutin::window::Fullscreen::Borderless(mh)));
-        }
+        let windowed_context = unsafe { windowed_context.make_current().map_err(|(_, err)| err)? };
     
         let gl = GlowContext::from_loader_function(|ptr| {
             windowed_context.get_proc_address(ptr) as *const _
         });
 
         Ok((Self {
-            el,
-            wc: windowed_context,
+            wc: Some(windowed_context),
+            context_id: 0,
         }, gl))
     }
 
+    /// Creates a window whose GL context shares objects with `shared`, with the
+    /// vsync, sRGB and acceleration of `hints`. The new context is left current.
+    pub fn new_shared<E>(
+        target: &EventLoopWindowTarget<E>,
+        shared: &Window,
+        gl_version: GlVersion,
+        hints: &InitHints,
+        title: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<Window> {
+        let wb = WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(width, height));
+
+        let request = match gl_version.api {
+            GlApi::OpenGl => GlRequest::Specific(Api::OpenGl, (gl_version.major, gl_version.minor)),
+            GlApi::OpenGlEs => GlRequest::Specific(Api::OpenGlEs, (gl_version.major, gl_version.minor)),
+        };
+
+        let mut builder = ContextBuilder::new()
+            .with_gl(request)
+            .with_hardware_acceleration(Some(hints.hardware_acceleration))
+            .with_vsync(hints.vsync)
+            .with_srgb(hints.srgb)
+            .with_shared_lists(shared.get_windowed_context().context());
+
+        if gl_version.api == GlApi::OpenGl && (gl_version.major, gl_version.minor) >= (3, 2) {
+            builder = builder.with_gl_profile(glutin::GlProfile::Core);
+        }
+
+        let windowed_context = builder.build_windowed(wb, target)?;
+        let windowed_context = unsafe { windowed_context.make_current().map_err(|(_, err)| err)? };
+
+        Ok(Self {
+            wc: Some(windowed_context),
+            context_id: 0,
+        })
+    }
+
+    fn inner(&self) -> &glutin::window::Window {
+        self.get_windowed_context().window()
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.inner().id()
+    }
+
+    pub fn swap_buffers(&self) -> Result<()> {
+        self.get_windowed_context().swap_buffers()?;
+        Ok(())
+    }
+
+    pub fn request_redraw(&self) {
+        self.inner().request_redraw();
+    }
+
+    pub fn get_windowed_context(&self) -> &glutin::WindowedContext<glutin::PossiblyCurrent> {
+        self.wc.as_ref().expect("Window context taken by make_current")
+    }
+
+    /// Makes the GL context of this window current on the calling thread.
+    pub(crate) fn make_current(&mut self) -> Result<()> {
+        if self.get_windowed_context().is_current() {
+            return Ok(());
+        }
+
+        let wc = self.wc.take().expect("Window context taken by make_current");
+        match unsafe { wc.make_current() } {
+            Ok(wc) => {
+                self.wc = Some(wc);
+                Ok(())
+            }
+            Err((wc, err)) => {
+                self.wc = Some(wc);
+                Err(err.into())
+            }
+        }
+    }
+
     pub fn get_pixel_format(&self) -> PixelFormat {
-        return self.wc.get_pixel_format();
+        self.get_windowed_context().get_pixel_format()
+    }
+
+    pub fn set_title(&self, title: &str) {
+        self.inner().set_title(title);
+    }
+
+    /// Sets the inner size, in logical pixels like `ContextBuilder::with_size`.
+    pub fn set_size(&self, width: u32, height: u32) {
+        self.inner().set_inner_size(LogicalSize::new(width, height));
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        let size: LogicalSize<u32> = self.inner().inner_size().to_logical(self.inner().scale_factor());
+        (size.width, size.height)
+    }
+
+    pub fn set_min_size(&self, size: Option<(u32, u32)>) {
+        self.inner().set_min_inner_size(size.map(|(w, h)| LogicalSize::new(w, h)));
     }
+
+    pub fn set_max_size(&self, size: Option<(u32, u32)>) {
+        self.inner().set_max_inner_size(size.map(|(w, h)| LogicalSize::new(w, h)));
+    }
+
+    /// Moves the top-left corner of the window, in logical pixels.
+    pub fn set_position(&self, x: i32, y: i32) {
+        self.inner().set_outer_position(LogicalPosition::new(x, y));
+    }
+
+    pub fn get_position(&self) -> Result<(i32, i32)> {
+        let position: LogicalPosition<i32> = self
+            .inner()
+            .outer_position()?
+            .to_logical(self.inner().scale_factor());
+        Ok((position.x, position.y))
+    }
+
+    pub fn set_resizable(&self, resizable: bool) {
+        self.inner().set_resizable(resizable);
+    }
+
+    pub fn set_decorations(&self, decorations: bool) {
+        self.inner().set_decorations(decorations);
+    }
+
+    pub fn set_always_on_top(&self, always_on_top: bool) {
+        self.inner().set_always_on_top(always_on_top);
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        self.inner().set_visible(visible);
+    }
+
+    pub fn set_icon(&self, rgba: Vec<u8>, width: u32, height: u32) -> Result<()> {
+        let icon = Icon::from_rgba(rgba, width, height)?;
+        self.inner().set_window_icon(Some(icon));
+        Ok(())
+    }
+
+    /// Loads the window icon from a PNG file.
+    pub fn set_icon_from_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let image = image::open(path)?.into_rgba8();
+        let (width, height) = image.dimensions();
+        self.set_icon(image.into_raw(), width, height)
+    }
+
+    pub fn clear_icon(&self) {
+        self.inner().set_window_icon(None);
+    }
+
+    pub fn set_cursor_visible(&self, visible: bool) {
+        self.inner().set_cursor_visible(visible);
+    }
+
+    /// Confines the cursor to the window.
+    pub fn set_cursor_grab(&self, grab: bool) -> Result<()> {
+        self.inner().set_cursor_grab(grab)?;
+        Ok(())
+    }
+
+    /// Picks one of the system cursors, the windowing backend can't load custom images.
+    pub fn set_cursor_icon(&self, cursor: CursorIcon) {
+        self.inner().set_cursor_icon(cursor);
+    }
+
+    /// Switches between windowed and borderless fullscreen on the current monitor.
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        let window = self.inner();
+        if fullscreen {
+            window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+        } else {
+            window.set_fullscreen(None);
+        }
+    }
+
+    /// Applies `mode` on `monitor`, or on the monitor the window is on when `None`.
+    pub fn set_fullscreen_mode(&self, mode: FullscreenMode, monitor: Option<&Monitor>) -> Result<()> {
+        let fullscreen = match monitor {
+            Some(monitor) => monitor.to_fullscreen(mode)?,
+            None => self.get_current_monitor().to_fullscreen(mode)?,
+        };
+        self.inner().set_fullscreen(fullscreen);
+        Ok(())
+    }
+
+    pub fn get_monitors(&self) -> Vec<Monitor> {
+        self.inner().available_monitors().map(Monitor::new).collect()
+    }
+
+    pub fn get_current_monitor(&self) -> Monitor {
+        Monitor::new(self.inner().current_monitor())
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.inner().fullscreen().is_some()
+    }
+
+    pub fn toggle_fullscreen(&self) {
+        self.set_fullscreen(!self.is_fullscreen());
+    }
+
+    /// Places the IME candidate window, in logical pixels from the top-left corner.
+    pub fn set_ime_position(&self, x: f64, y: f64) {
+        self.inner().set_ime_position(LogicalPosition::new(x, y));
+    }
+}
+
+/// Tries each of `ContextBuilder::with_gl_versions` in order, keeping the first one created.
+fn build_versioned_context<E>(
+    settings: &context::ContextBuilder,
+    wb: WindowBuilder,
+    el: &EventLoop<E>,
+) -> Result<glutin::WindowedContext<glutin::NotCurrent>> {
+    let hints = &settings.platform_hints;
+    let mut errors = Vec::new();
+
+    for version in &settings.gl_versions {
+        let request = match version.api {
+            GlApi::OpenGl => GlRequest::Specific(Api::OpenGl, (version.major, version.minor)),
+            GlApi::OpenGlEs => GlRequest::Specific(Api::OpenGlEs, (version.major, version.minor)),
+        };
+
+        let mut builder = ContextBuilder::new()
+            .with_gl(request)
+            .with_hardware_acceleration(Some(hints.hardware_acceleration))
+            .with_vsync(hints.vsync)
+            .with_srgb(hints.srgb);
+
+        if version.api == GlApi::OpenGl && (version.major, version.minor) >= (3, 2) {
+            builder = builder.with_gl_profile(hints.gl_profile);
+        }
+
+        match builder.build_windowed(wb.clone(), el) {
+            Ok(windowed_context) => {
+                info!("Created GL context for {:?}", version);
+                return Ok(windowed_context);
+            }
+            Err(err) => {
+                warn!("Failed to create GL context for {:?}: {}", version, err);
+                errors.push(format!("{:?}: {}", version, err));
+            }
+        }
+    }
+
+    Err(failure::err_msg(format!("No requested GL version available ({})", errors.join(", "))).into())
+}
+
+/// Picks the monitor at `index` in the available monitors, the primary one when `None`.
+fn select_monitor<E>(el: &EventLoop<E>, index: Option<usize>) -> Result<Monitor> {
+    let monitors: Vec<_> = el.available_monitors().collect();
+
+    let handle = match index {
+        Some(index) => monitors.get(index).cloned().ok_or_else(|| {
+            failure::err_msg(format!(
+                "Monitor {} requested but only {} available",
+                index,
+                monitors.len()
+            ))
+        })?,
+        None if monitors.is_empty() => {
+            return Err(failure::err_msg("No monitor available for fullscreen").into());
+        }
+        None => {
+            let primary = el.primary_monitor();
+            if monitors.contains(&primary) {
+                primary
+            } else {
+                monitors[0].clone()
+            }
+        }
+    };
+
+    Ok(Monitor::new(handle))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InitHints {
     pub vsync: bool,
-    pub fullscreen: bool,
+    pub fullscreen: FullscreenMode,
+    /// Index of the monitor used for fullscreen, the primary monitor when `None`.
+    pub monitor: Option<usize>,
     pub gl_version: glutin::GlRequest,
     pub gl_profile: glutin::GlProfile,
     pub hardware_acceleration: bool,
@@ -65,7 +353,8 @@ impl Default for InitHints {
     fn default() -> Self {
         Self {
             vsync: true,
-            fullscreen: false,
+            fullscreen: FullscreenMode::Windowed,
+            monitor: None,
             gl_version: glutin::GlRequest::Latest,
             gl_profile: glutin::GlProfile::Core,
             hardware_acceleration: true,